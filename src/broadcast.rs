@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::NodeContext,
+    crdt::GSet,
+    gossip::{DeltaSync, GossipConfig},
+    topology::{Neighbours, Overlay},
+    Message, Node,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum BroadcastPayload {
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    ReadOk {
+        messages: Vec<usize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+    Propogate {
+        messages: GSet<usize>,
+        upto: u64,
+    },
+    PropogateOk {
+        upto: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BroadcastTimer {
+    Gossip,
+}
+
+/// A node of the `broadcast` workload that gossips the messages it knows of
+/// as a grow-only set.
+///
+/// Every `GossipConfig::interval` it sends each neighbour the deltas that
+/// neighbour has not acknowledged yet, so lost gossip is sent again and
+/// acknowledged gossip never is. Neighbours come from the `Overlay`.
+pub struct BroadcastNode {
+    neighbours: Neighbours,
+    messages: DeltaSync<GSet<usize>>,
+}
+
+impl BroadcastNode {
+    fn gossip(&mut self, ctx: &mut NodeContext<BroadcastTimer>) -> anyhow::Result<()> {
+        for (node, upto, messages) in self.messages.batches(ctx.rng()) {
+            ctx.send(node, BroadcastPayload::Propogate { messages, upto })?;
+        }
+        Ok(())
+    }
+}
+
+impl Node<BroadcastPayload, (Overlay, GossipConfig), BroadcastTimer> for BroadcastNode {
+    fn new(
+        (overlay, config): (Overlay, GossipConfig),
+        ctx: &mut NodeContext<BroadcastTimer>,
+    ) -> Self {
+        ctx.timers.every(BroadcastTimer::Gossip, config.interval);
+        let neighbours = Neighbours::new(overlay, &ctx.node_id, &ctx.node_ids);
+        BroadcastNode {
+            messages: DeltaSync::new(config, neighbours.nodes.clone()),
+            neighbours,
+        }
+    }
+
+    fn handle(
+        &mut self,
+        message: Message<BroadcastPayload>,
+        ctx: &mut NodeContext<BroadcastTimer>,
+    ) -> anyhow::Result<()> {
+        match &message.body.payload {
+            BroadcastPayload::Broadcast { message: data } => {
+                self.messages.mutate(|messages| messages.insert(*data));
+                ctx.reply(&message, BroadcastPayload::BroadcastOk)?;
+            }
+            BroadcastPayload::BroadcastOk => {}
+            BroadcastPayload::Read => {
+                ctx.reply(
+                    &message,
+                    BroadcastPayload::ReadOk {
+                        messages: self.messages.state().iter().copied().collect(),
+                    },
+                )?;
+            }
+            BroadcastPayload::ReadOk { messages: _ } => {}
+            BroadcastPayload::Topology { topology } => {
+                self.neighbours.on_topology(topology);
+                self.messages.set_peers(self.neighbours.nodes.clone());
+                ctx.reply(&message, BroadcastPayload::TopologyOk)?;
+            }
+            BroadcastPayload::TopologyOk => {}
+            BroadcastPayload::Propogate { messages, upto } => {
+                self.messages.receive(messages.clone());
+                ctx.reply(&message, BroadcastPayload::PropogateOk { upto: *upto })?;
+            }
+            BroadcastPayload::PropogateOk { upto } => {
+                self.messages.ack(&message.src, *upto);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_timer(
+        &mut self,
+        timer: BroadcastTimer,
+        ctx: &mut NodeContext<BroadcastTimer>,
+    ) -> anyhow::Result<()> {
+        match timer {
+            BroadcastTimer::Gossip => self.gossip(ctx),
+        }
+    }
+}
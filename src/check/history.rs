@@ -0,0 +1,217 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+};
+
+use serde_json::Value;
+
+use crate::{error::ErrorCode, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Invoke,
+    /// The operation took place.
+    Ok,
+    /// The operation is known not to have taken place.
+    Fail,
+    /// The operation may or may not have taken place.
+    Info,
+}
+
+/// A client request or its outcome.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// The position in the history.
+    pub index: usize,
+    pub kind: EventKind,
+    /// The client, or whatever else issued the operation.
+    pub process: String,
+    /// The `msg_id` of the request, pairing an invocation with its outcome.
+    pub id: usize,
+    /// The payload of the request or of the reply.
+    pub body: Value,
+}
+
+/// A client operation, from its invocation to its outcome.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub process: String,
+    /// Ok, fail or info.
+    pub kind: EventKind,
+    pub request: Value,
+    /// `None` if the operation never completed.
+    pub reply: Option<Value>,
+    pub invoke: usize,
+    pub complete: Option<usize>,
+}
+
+impl Operation {
+    pub fn kind_of(&self) -> &str {
+        self.request
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("?")
+    }
+
+    /// The code of an `error` reply.
+    pub fn error(&self) -> Option<ErrorCode> {
+        let reply = self.reply.as_ref()?;
+        if reply.get("type").and_then(Value::as_str) != Some("error") {
+            return None;
+        }
+        let code = reply.get("code").and_then(Value::as_u64)?;
+        Some(ErrorCode::from_code(code as u32))
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let complete = self
+            .complete
+            .map_or_else(|| "-".to_string(), |c| c.to_string());
+        write!(
+            f,
+            "[{:>6}, {:>6}] {} {:?} {}",
+            self.invoke, complete, self.process, self.kind, self.request
+        )?;
+        if let Some(reply) = &self.reply {
+            write!(f, " -> {}", reply)?;
+        }
+        Ok(())
+    }
+}
+
+/// The client operations of a run, as `invoke` and `ok`, `fail` or `info`
+/// events in the order they happened.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+    pub events: Vec<Event>,
+}
+
+impl History {
+    /// Reads the client requests and replies out of a message stream: one
+    /// message per line, as written by `Message::send`, as traced by
+    /// `LOG_MESSAGES` or as journaled by the simulator. Lines that hold no
+    /// message are skipped, and so are repeats of a message, such as a
+    /// trace showing it on both ends.
+    ///
+    /// Clients are the senders named `c` and a number, the way maelstrom
+    /// names them. A request without a reply is left without an outcome.
+    /// Error replies fail the operation if the code is definite and leave
+    /// it `info` otherwise, any other reply completes it as `ok`.
+    pub fn from_messages(stream: &str) -> Self {
+        let mut history = History::default();
+        let mut seen = HashSet::new();
+        let mut pending = HashSet::new();
+        for line in stream.lines() {
+            let Some(message) = line
+                .find('{')
+                .and_then(|start| serde_json::from_str::<Message<Value>>(&line[start..]).ok())
+            else {
+                continue;
+            };
+            let body = message.body;
+            if is_client(&message.src) {
+                let Some(id) = body.msg_id else { continue };
+                if !seen.insert((message.src.clone(), id)) {
+                    continue;
+                }
+                pending.insert((message.src.clone(), id));
+                history.push(EventKind::Invoke, message.src, id, body.payload);
+            } else if is_client(&message.dest) {
+                let Some(id) = body.in_reply_to else { continue };
+                if !pending.remove(&(message.dest.clone(), id)) {
+                    continue;
+                }
+                let kind = outcome(&body.payload);
+                history.push(kind, message.dest, id, body.payload);
+            }
+        }
+        history
+    }
+
+    pub fn push(&mut self, kind: EventKind, process: String, id: usize, body: Value) {
+        self.events.push(Event {
+            index: self.events.len(),
+            kind,
+            process,
+            id,
+            body,
+        });
+    }
+
+    /// Pairs every invocation with its outcome. Invocations that never
+    /// completed become `info` operations.
+    pub fn operations(&self) -> Vec<Operation> {
+        let mut operations = Vec::new();
+        let mut open: HashMap<(&str, usize), usize> = HashMap::new();
+        for event in &self.events {
+            let key = (event.process.as_str(), event.id);
+            if event.kind == EventKind::Invoke {
+                open.insert(key, operations.len());
+                operations.push(Operation {
+                    process: event.process.clone(),
+                    kind: EventKind::Info,
+                    request: event.body.clone(),
+                    reply: None,
+                    invoke: event.index,
+                    complete: None,
+                });
+            } else if let Some(i) = open.remove(&key) {
+                let operation = &mut operations[i];
+                operation.kind = event.kind;
+                operation.reply = Some(event.body.clone());
+                operation.complete = Some(event.index);
+            }
+        }
+        operations
+    }
+}
+
+/// How an operation ended, going by its reply: `fail` for an error with a
+/// definite code, `info` for any other error and `ok` otherwise.
+pub fn outcome(reply: &Value) -> EventKind {
+    match reply.get("code").and_then(Value::as_u64) {
+        Some(code) if reply.get("type") == Some(&"error".into()) => {
+            if ErrorCode::from_code(code as u32).is_definite() {
+                EventKind::Fail
+            } else {
+                EventKind::Info
+            }
+        }
+        _ => EventKind::Ok,
+    }
+}
+
+/// Whether `id` names a client, `c` and a number.
+pub fn is_client(id: &str) -> bool {
+    id.strip_prefix('c')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_requests_with_replies_from_a_trace() {
+        let stream = r#"
+[n0]  in: {"src":"c1","dest":"n0","body":{"type":"write","key":1,"value":2,"msg_id":1}}
+{"src":"n0","dest":"n1","body":{"type":"read","key":1,"msg_id":7}}
+{"src":"c2","dest":"n1","body":{"type":"read","key":1,"msg_id":1}}
+[n0] out: {"src":"n0","dest":"c1","body":{"type":"write_ok","msg_id":3,"in_reply_to":1}}
+[n1]  in: {"src":"n0","dest":"c1","body":{"type":"write_ok","msg_id":3,"in_reply_to":1}}
+{"src":"c3","dest":"n1","body":{"type":"cas","key":1,"from":1,"to":3,"msg_id":1}}
+{"src":"n1","dest":"c3","body":{"type":"error","code":22,"text":"","in_reply_to":1}}
+"#;
+        let ops = History::from_messages(stream).operations();
+        assert_eq!(ops.len(), 3);
+        assert_eq!(
+            (ops[0].kind, ops[0].invoke, ops[0].complete),
+            (EventKind::Ok, 0, Some(2))
+        );
+        assert_eq!((ops[1].kind, ops[1].complete), (EventKind::Info, None));
+        assert_eq!(ops[2].kind, EventKind::Fail);
+        assert_eq!(ops[2].error(), Some(ErrorCode::PreconditionFailed));
+    }
+}
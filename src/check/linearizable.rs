@@ -0,0 +1,431 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::{self, Display},
+};
+
+use serde_json::Value;
+
+use crate::{
+    check::history::{EventKind, History, Operation},
+    error::ErrorCode,
+};
+
+/// What an operation does to a single register, with values interned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Call {
+    /// Observed the value, `None` if the key did not exist.
+    Read(Option<usize>),
+    Write(usize),
+    Cas(usize, usize),
+}
+
+/// An operation on one key. `complete` is `usize::MAX` for operations that
+/// may still take effect at any later point.
+#[derive(Debug, Clone, Copy)]
+struct Op {
+    operation: usize,
+    call: Call,
+    info: bool,
+    invoke: usize,
+    complete: usize,
+}
+
+impl Op {
+    /// Applies the operation to `state`, `None` if it cannot have returned
+    /// what it did from there. An indeterminate `cas` takes effect only if
+    /// it can.
+    fn step(&self, state: Option<usize>) -> Option<Option<usize>> {
+        match self.call {
+            Call::Read(value) => (state == value).then_some(state),
+            Call::Write(value) => Some(Some(value)),
+            Call::Cas(from, to) if state == Some(from) => Some(Some(to)),
+            Call::Cas(_, _) if self.info => Some(state),
+            Call::Cas(_, _) => None,
+        }
+    }
+}
+
+/// The smallest part of a history found that still has no linearization,
+/// all on one key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample {
+    pub key: Value,
+    pub operations: Vec<Operation>,
+}
+
+impl Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "key {} is not linearizable, no order of these {} operations is valid:",
+            self.key,
+            self.operations.len()
+        )?;
+        for operation in &self.operations {
+            writeln!(f, "  {}", operation)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Counterexample {}
+
+/// Checks a history of the lin-kv workload, `read`, `write` and `cas` on
+/// keys that start out missing, for linearizability.
+///
+/// Every key is an independent register, so keys are checked one at a time.
+/// The search is Wing and Gong's: it linearizes the operations one by one,
+/// only ever picking one invoked before every remaining operation
+/// completed, and backtracks when the register cannot have answered what it
+/// did. Like Lowe's variant it remembers every set of linearized operations
+/// and register value it has been at, so no state is explored twice. Failed
+/// operations never happened and are left out, operations without a definite
+/// outcome may take effect at any point after their invocation or never.
+///
+/// A history that does not pass is shrunk to a minimal counterexample, one
+/// that would pass without any one of its operations. Writes of the values
+/// it reads are kept, so it shows where every value came from, unless
+/// nothing in the history wrote it.
+pub fn check(history: &History) -> anyhow::Result<Option<Counterexample>> {
+    let operations = history.operations();
+    let mut keys: BTreeMap<String, (Value, Vec<Op>)> = BTreeMap::new();
+    let mut values = Vec::new();
+    for (i, operation) in operations.iter().enumerate() {
+        let Some(op) = to_op(i, operation, &mut values)? else {
+            continue;
+        };
+        let key = operation.request.get("key").cloned().unwrap_or(Value::Null);
+        keys.entry(key.to_string())
+            .or_insert_with(|| (key, Vec::new()))
+            .1
+            .push(op);
+    }
+
+    for (key, ops) in keys.into_values() {
+        if linearizable(&ops) {
+            continue;
+        }
+        let ops = shrink(ops);
+        return Ok(Some(Counterexample {
+            key,
+            operations: ops
+                .iter()
+                .map(|op| operations[op.operation].clone())
+                .collect(),
+        }));
+    }
+    Ok(None)
+}
+
+/// Turns an operation into what it did to its key, `None` if it did nothing.
+fn to_op(i: usize, operation: &Operation, values: &mut Vec<Value>) -> anyhow::Result<Option<Op>> {
+    let request = &operation.request;
+    let mut field = |name: &str| -> anyhow::Result<usize> {
+        let value = request.get(name).ok_or_else(|| {
+            anyhow::anyhow!("{} without {}: {}", operation.kind_of(), name, request)
+        })?;
+        Ok(intern(values, value))
+    };
+    let missing = operation.error() == Some(ErrorCode::KeyDoesNotExist);
+    let call = match (operation.kind_of(), operation.kind) {
+        ("read", EventKind::Ok) => {
+            let value = operation.reply.as_ref().and_then(|r| r.get("value"));
+            Call::Read(value.map(|v| intern(values, v)))
+        }
+        // A read of a missing key fails with a definite error, but it did
+        // observe the key.
+        ("read", EventKind::Fail) if missing => Call::Read(None),
+        ("read", _) | (_, EventKind::Fail) => return Ok(None),
+        ("write", _) => Call::Write(field("value")?),
+        ("cas", _) => Call::Cas(field("from")?, field("to")?),
+        (other, _) => return Err(anyhow::anyhow!("unknown operation {:?}", other)),
+    };
+    let info = operation.kind == EventKind::Info;
+    Ok(Some(Op {
+        operation: i,
+        call,
+        info,
+        invoke: operation.invoke,
+        complete: if info {
+            usize::MAX
+        } else {
+            operation.complete.unwrap_or(usize::MAX)
+        },
+    }))
+}
+
+fn intern(values: &mut Vec<Value>, value: &Value) -> usize {
+    match values.iter().position(|v| v == value) {
+        Some(i) => i,
+        None => {
+            values.push(value.clone());
+            values.len() - 1
+        }
+    }
+}
+
+/// A frame of the search: the operations linearized so far, the register
+/// value they leave, and the candidates still to try from there.
+struct Frame {
+    done: Vec<u64>,
+    count: usize,
+    state: Option<usize>,
+    candidates: Vec<usize>,
+}
+
+/// Whether the register can have run `ops`, sorted by invocation, in some
+/// order.
+fn linearizable(ops: &[Op]) -> bool {
+    let done = vec![0; ops.len().div_ceil(64)];
+    let candidates = next_candidates(&done, None, ops);
+    let mut stack = vec![Frame {
+        done,
+        count: 0,
+        state: None,
+        candidates,
+    }];
+    let mut seen: HashSet<(Vec<u64>, Option<usize>)> = HashSet::new();
+    while let Some(frame) = stack.last_mut() {
+        if frame.count == ops.len() {
+            return true;
+        }
+        let Some(i) = frame.candidates.pop() else {
+            stack.pop();
+            continue;
+        };
+        let Some(state) = ops[i].step(frame.state) else {
+            continue;
+        };
+        let mut done = frame.done.clone();
+        done[i / 64] |= 1 << (i % 64);
+        if !seen.insert((done.clone(), state)) {
+            continue;
+        }
+        let next = Frame {
+            candidates: next_candidates(&done, state, ops),
+            count: frame.count + 1,
+            done,
+            state,
+        };
+        stack.push(next);
+    }
+    false
+}
+
+/// The operations that can go next from `state`: the ones not `done` that
+/// were invoked before every one of those completed. As `ops` are sorted by
+/// invocation, the scan stops at the first one invoked after a completion.
+///
+/// A read that can go next and observes `state` is the only candidate.
+/// Nothing has to go before it and it leaves the register as it is, so if
+/// there is a linearization from here, there is one starting with it.
+fn next_candidates(done: &[u64], state: Option<usize>, ops: &[Op]) -> Vec<usize> {
+    let mut deadline = usize::MAX;
+    let mut candidates = Vec::new();
+    'scan: for (w, word) in done.iter().enumerate() {
+        let mut remaining = !word;
+        while remaining != 0 {
+            let i = w * 64 + remaining.trailing_zeros() as usize;
+            if i >= ops.len() || ops[i].invoke >= deadline {
+                break 'scan;
+            }
+            candidates.push(i);
+            deadline = deadline.min(ops[i].complete);
+            remaining &= remaining - 1;
+        }
+    }
+    candidates.retain(|i| ops[*i].invoke < deadline);
+    if let Some(read) = candidates
+        .iter()
+        .find(|i| ops[**i].call == Call::Read(state))
+    {
+        return vec![*read];
+    }
+    // Tried from the back, so the earliest invocation goes first.
+    candidates.reverse();
+    candidates
+}
+
+/// Removes operations from a history without a linearization for as long
+/// as it stays that way: first everything after the shortest failing
+/// prefix a bisection finds, then chunks of halving size, down to single
+/// operations.
+fn shrink(mut ops: Vec<Op>) -> Vec<Op> {
+    let written = written(&ops);
+    let fails = |ops: &[Op]| justified(ops, &written) && !linearizable(ops);
+
+    let (mut shortest, mut longest) = (1, ops.len());
+    while shortest < longest {
+        let middle = (shortest + longest) / 2;
+        if fails(&ops[..middle]) {
+            longest = middle;
+        } else {
+            shortest = middle + 1;
+        }
+    }
+    ops.truncate(longest);
+
+    let mut chunk = ops.len().div_ceil(2).max(1);
+    loop {
+        let mut removed = false;
+        let mut i = 0;
+        while i < ops.len() {
+            let mut smaller = ops.clone();
+            smaller.drain(i..(i + chunk).min(ops.len()));
+            if fails(&smaller) {
+                ops = smaller;
+                removed = true;
+            } else {
+                i += chunk;
+            }
+        }
+        if chunk == 1 && !removed {
+            return ops;
+        }
+        chunk = (chunk / 2).max(1);
+    }
+}
+
+fn written(ops: &[Op]) -> HashSet<usize> {
+    ops.iter()
+        .filter_map(|op| match op.call {
+            Call::Write(value) | Call::Cas(_, value) => Some(value),
+            Call::Read(_) => None,
+        })
+        .collect()
+}
+
+/// Whether every value of `ops` that is read or compared against, and is
+/// among `values`, is written by one of `ops`.
+fn justified(ops: &[Op], values: &HashSet<usize>) -> bool {
+    let written = written(ops);
+    ops.iter().all(|op| match op.call {
+        Call::Read(Some(value)) | Call::Cas(value, _) => {
+            !values.contains(&value) || written.contains(&value)
+        }
+        Call::Read(None) | Call::Write(_) => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Builds a history one event at a time.
+    struct Builder {
+        history: History,
+        next_id: usize,
+    }
+
+    impl Builder {
+        fn new() -> Self {
+            Builder {
+                history: History::default(),
+                next_id: 0,
+            }
+        }
+
+        fn invoke(&mut self, process: &str, request: Value) -> usize {
+            self.next_id += 1;
+            let id = self.next_id;
+            self.history
+                .push(EventKind::Invoke, process.to_string(), id, request);
+            id
+        }
+
+        fn complete(&mut self, process: &str, id: usize, kind: EventKind, reply: Value) {
+            self.history.push(kind, process.to_string(), id, reply);
+        }
+    }
+
+    fn write(key: u64, value: u64) -> Value {
+        json!({"type": "write", "key": key, "value": value})
+    }
+
+    fn read(key: u64) -> Value {
+        json!({"type": "read", "key": key})
+    }
+
+    fn read_ok(value: u64) -> Value {
+        json!({"type": "read_ok", "value": value})
+    }
+
+    #[test]
+    fn concurrent_operations_may_take_effect_in_either_order() {
+        let mut b = Builder::new();
+        let w1 = b.invoke("c1", write(1, 1));
+        let w2 = b.invoke("c2", write(1, 2));
+        let r = b.invoke("c3", read(1));
+        b.complete("c3", r, EventKind::Ok, read_ok(1));
+        b.complete("c1", w1, EventKind::Ok, json!({"type": "write_ok"}));
+        b.complete("c2", w2, EventKind::Ok, json!({"type": "write_ok"}));
+        let r = b.invoke("c3", read(1));
+        b.complete("c3", r, EventKind::Ok, read_ok(1));
+        assert_eq!(check(&b.history).unwrap(), None);
+    }
+
+    #[test]
+    fn stale_reads_are_caught_and_shrunk() {
+        let mut b = Builder::new();
+        for key in [2, 1] {
+            let w = b.invoke("c1", write(key, 1));
+            b.complete("c1", w, EventKind::Ok, json!({"type": "write_ok"}));
+        }
+        let w = b.invoke("c1", write(1, 2));
+        b.complete("c1", w, EventKind::Ok, json!({"type": "write_ok"}));
+        let r = b.invoke("c2", read(2));
+        b.complete("c2", r, EventKind::Ok, read_ok(1));
+        let r = b.invoke("c2", read(1));
+        b.complete("c2", r, EventKind::Ok, read_ok(1));
+
+        let counterexample = check(&b.history).unwrap().unwrap();
+        assert_eq!(counterexample.key, json!(1));
+        let requests: Vec<&Value> = counterexample
+            .operations
+            .iter()
+            .map(|op| &op.request)
+            .collect();
+        assert_eq!(requests, [&write(1, 1), &write(1, 2), &read(1)]);
+    }
+
+    #[test]
+    fn indeterminate_operations_may_or_may_not_happen() {
+        let mut b = Builder::new();
+        let w = b.invoke("c1", write(1, 1));
+        b.complete("c1", w, EventKind::Ok, json!({"type": "write_ok"}));
+        b.invoke("c2", json!({"type": "cas", "key": 1, "from": 1, "to": 5}));
+        let r = b.invoke("c3", read(1));
+        b.complete("c3", r, EventKind::Ok, read_ok(5));
+        let r = b.invoke("c3", read(1));
+        b.complete("c3", r, EventKind::Ok, read_ok(5));
+        assert_eq!(check(&b.history).unwrap(), None);
+
+        // A failed cas never happened, so nothing could have written 5.
+        let mut b = Builder::new();
+        let w = b.invoke("c1", write(1, 1));
+        b.complete("c1", w, EventKind::Ok, json!({"type": "write_ok"}));
+        let c = b.invoke("c2", json!({"type": "cas", "key": 1, "from": 1, "to": 5}));
+        let error = json!({"type": "error", "code": 11, "text": ""});
+        b.complete("c2", c, EventKind::Fail, error);
+        let r = b.invoke("c3", read(1));
+        b.complete("c3", r, EventKind::Ok, read_ok(5));
+        assert!(check(&b.history).unwrap().is_some());
+    }
+
+    #[test]
+    fn missing_keys_read_as_absent() {
+        let mut b = Builder::new();
+        let r = b.invoke("c1", read(1));
+        let error = json!({"type": "error", "code": 20, "text": ""});
+        b.complete("c1", r, EventKind::Fail, error.clone());
+        let w = b.invoke("c2", write(1, 1));
+        b.complete("c2", w, EventKind::Ok, json!({"type": "write_ok"}));
+        assert_eq!(check(&b.history).unwrap(), None);
+
+        let r = b.invoke("c1", read(1));
+        b.complete("c1", r, EventKind::Fail, error);
+        assert!(check(&b.history).unwrap().is_some());
+    }
+}
@@ -0,0 +1,852 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use serde_json::Value;
+
+use crate::check::history::{EventKind, History, Operation};
+
+/// The transactional workloads the checker understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    /// `["append", k, v]` adds `v` to the end of the list at `k` and
+    /// `["r", k, null]` reads the whole list. Every value is appended once.
+    ListAppend,
+    /// `["w", k, v]` and `["r", k, null]` on registers. Every value is
+    /// written once.
+    RwRegister,
+}
+
+impl FromStr for Workload {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "txn-list-append" => Ok(Workload::ListAppend),
+            "txn-rw-register" => Ok(Workload::RwRegister),
+            _ => Err(anyhow::anyhow!("unknown workload {:?}", s)),
+        }
+    }
+}
+
+/// Isolation levels, weakest first. Every level proscribes the anomalies
+/// the ones before it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    ReadUncommitted,
+    ReadCommitted,
+    SnapshotIsolation,
+    Serializable,
+}
+
+const LEVELS: [Level; 4] = [
+    Level::ReadUncommitted,
+    Level::ReadCommitted,
+    Level::SnapshotIsolation,
+    Level::Serializable,
+];
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::ReadUncommitted => "read-uncommitted",
+            Level::ReadCommitted => "read-committed",
+            Level::SnapshotIsolation => "snapshot-isolation",
+            Level::Serializable => "serializable",
+        })
+    }
+}
+
+/// Why one transaction has to come before another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dependency {
+    /// It wrote a version the other one overwrote.
+    Ww,
+    /// It wrote a version the other one read.
+    Wr,
+    /// It read a version the other one overwrote.
+    Rw,
+}
+
+impl Dependency {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Dependency::Ww => "ww",
+            Dependency::Wr => "wr",
+            Dependency::Rw => "rw",
+        })
+    }
+}
+
+/// The anomalies of Adya's thesis the checker looks for, and the ones that
+/// make a history impossible to explain at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// A cycle of ww dependencies.
+    G0,
+    /// A read of a write that failed.
+    G1a,
+    /// A read of a version its transaction overwrote itself.
+    G1b,
+    /// A cycle of ww and wr dependencies.
+    G1c,
+    /// A cycle with exactly one rw dependency.
+    GSingle,
+    /// A cycle with more than one rw dependency.
+    G2,
+    /// A transaction that did not read its own writes, or read a key
+    /// differently twice.
+    Internal,
+    /// Two reads of a list where neither is a prefix of the other.
+    IncompatibleOrder,
+    /// A read of a value nothing wrote.
+    Garbage,
+}
+
+impl AnomalyKind {
+    /// The weakest isolation level that rules the anomaly out.
+    pub fn proscribed_by(self) -> Level {
+        match self {
+            AnomalyKind::G0
+            | AnomalyKind::Internal
+            | AnomalyKind::IncompatibleOrder
+            | AnomalyKind::Garbage => Level::ReadUncommitted,
+            AnomalyKind::G1a | AnomalyKind::G1b | AnomalyKind::G1c => Level::ReadCommitted,
+            AnomalyKind::GSingle => Level::SnapshotIsolation,
+            AnomalyKind::G2 => Level::Serializable,
+        }
+    }
+}
+
+impl Display for AnomalyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AnomalyKind::G0 => "G0",
+            AnomalyKind::G1a => "G1a",
+            AnomalyKind::G1b => "G1b",
+            AnomalyKind::G1c => "G1c",
+            AnomalyKind::GSingle => "G-single",
+            AnomalyKind::G2 => "G2",
+            AnomalyKind::Internal => "internal",
+            AnomalyKind::IncompatibleOrder => "incompatible-order",
+            AnomalyKind::Garbage => "garbage-read",
+        })
+    }
+}
+
+/// An anomaly and the transactions involved, `T0`, `T1` and so on in the
+/// explanation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub kind: AnomalyKind,
+    pub explanation: String,
+    pub operations: Vec<Operation>,
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {}", self.kind, self.explanation)?;
+        for (i, operation) in self.operations.iter().enumerate() {
+            writeln!(f, "  T{} {}", i, operation)?;
+        }
+        Ok(())
+    }
+}
+
+/// What the checker found: at most one cycle of every kind, and every
+/// other anomaly.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+
+    /// The isolation levels the history shows the system does not provide.
+    pub fn violated(&self) -> Vec<Level> {
+        LEVELS
+            .into_iter()
+            .filter(|level| {
+                self.anomalies
+                    .iter()
+                    .any(|anomaly| anomaly.kind.proscribed_by() <= *level)
+            })
+            .collect()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let violated: Vec<String> = self.violated().iter().map(Level::to_string).collect();
+        writeln!(f, "violates {}", violated.join(", "))?;
+        for anomaly in &self.anomalies {
+            write!(f, "{}", anomaly)?;
+        }
+        Ok(())
+    }
+}
+
+/// A micro-op, with keys and written values as JSON text.
+#[derive(Debug, Clone, PartialEq)]
+enum Mop {
+    Read(String, Value),
+    Write(String, String),
+    Append(String, String),
+}
+
+#[derive(Debug, Clone)]
+struct Txn {
+    operation: usize,
+    kind: EventKind,
+    mops: Vec<Mop>,
+}
+
+type Version = (String, String);
+
+/// The transaction that read a list, what it read, and whether that was
+/// before it appended to the list itself.
+type ListRead = (usize, Vec<String>, bool);
+
+/// Checks a history of `txn` operations for the anomalies of Adya's
+/// thesis, the way Elle does.
+///
+/// Every value is written once, so a read tells which transaction wrote
+/// what it saw. From the reads the checker infers the order the versions of
+/// every key were installed in: a list read shows every append before the
+/// last one, a register starts out missing and a transaction that read a
+/// register and wrote it installed its version right after the one it read.
+/// That gives the dependencies between transactions, and a cycle of them
+/// means no serial order explains the history. Cycles are classified by the
+/// dependencies they need, G0 with ww only, G1c with ww and wr, G-single
+/// with a single rw and G2 with more.
+///
+/// Failed transactions never happened. Transactions without a definite
+/// outcome may have happened, so their writes can be read, but nothing
+/// is known about their reads.
+pub fn check(history: &History, workload: Workload) -> anyhow::Result<Report> {
+    let operations = history.operations();
+    let mut txns = Vec::new();
+    for (i, operation) in operations.iter().enumerate() {
+        txns.push(to_txn(i, operation)?);
+    }
+    let mut checker = Checker {
+        operations: &operations,
+        txns: &txns,
+        writers: HashMap::new(),
+        intermediate: HashMap::new(),
+        failed: HashMap::new(),
+        edges: vec![BTreeMap::new(); txns.len()],
+        anomalies: Vec::new(),
+    };
+    match workload {
+        Workload::ListAppend => checker.list_append()?,
+        Workload::RwRegister => checker.rw_register(),
+    }
+    checker.cycles();
+    Ok(Report {
+        anomalies: checker.anomalies,
+    })
+}
+
+/// Reads the micro-ops out of the reply of a committed transaction, or out
+/// of the request of any other, without the reads.
+fn to_txn(i: usize, operation: &Operation) -> anyhow::Result<Txn> {
+    if operation.kind_of() != "txn" {
+        return Err(anyhow::anyhow!(
+            "unknown operation {:?}",
+            operation.kind_of()
+        ));
+    }
+    let body = match (&operation.reply, operation.kind) {
+        (Some(reply), EventKind::Ok) => reply,
+        _ => &operation.request,
+    };
+    let malformed = || anyhow::anyhow!("malformed txn: {}", body);
+    let mut mops = Vec::new();
+    for mop in body
+        .get("txn")
+        .and_then(Value::as_array)
+        .ok_or_else(malformed)?
+    {
+        let [f, key, value] = mop.as_array().map(Vec::as_slice).ok_or_else(malformed)? else {
+            return Err(malformed());
+        };
+        let key = key.to_string();
+        mops.push(match f.as_str() {
+            Some("r") if operation.kind == EventKind::Ok => Mop::Read(key, value.clone()),
+            Some("r") => continue,
+            Some("w") => Mop::Write(key, value.to_string()),
+            Some("append") => Mop::Append(key, value.to_string()),
+            _ => return Err(malformed()),
+        });
+    }
+    Ok(Txn {
+        operation: i,
+        kind: operation.kind,
+        mops,
+    })
+}
+
+struct Checker<'a> {
+    operations: &'a [Operation],
+    txns: &'a [Txn],
+    /// The transaction that installed every version.
+    writers: HashMap<Version, usize>,
+    /// Versions a transaction overwrote itself, so nobody else may see.
+    intermediate: HashMap<Version, usize>,
+    /// Versions written by failed transactions.
+    failed: HashMap<Version, usize>,
+    /// The dependencies between transactions, with a key that shows each.
+    edges: Vec<BTreeMap<usize, BTreeMap<Dependency, String>>>,
+    anomalies: Vec<Anomaly>,
+}
+
+impl Checker<'_> {
+    fn list_append(&mut self) -> anyhow::Result<()> {
+        for (t, txn) in self.txns.iter().enumerate() {
+            let mut last: HashMap<&str, &str> = HashMap::new();
+            for mop in &txn.mops {
+                let Mop::Append(key, value) = mop else {
+                    continue;
+                };
+                self.wrote(t, key, value);
+                if let Some(previous) = last.insert(key, value) {
+                    self.intermediate
+                        .insert((key.clone(), previous.to_string()), t);
+                }
+            }
+        }
+
+        let mut reads: BTreeMap<&str, Vec<ListRead>> = BTreeMap::new();
+        for (t, txn) in self.txns.iter().enumerate() {
+            let mut appended = HashSet::new();
+            for mop in &txn.mops {
+                match mop {
+                    Mop::Append(key, _) => {
+                        appended.insert(key.as_str());
+                    }
+                    Mop::Read(key, value) => {
+                        let list = match value {
+                            Value::Null => Vec::new(),
+                            Value::Array(list) => list.iter().map(Value::to_string).collect(),
+                            _ => return Err(anyhow::anyhow!("read of a list as {}", value)),
+                        };
+                        let external = !appended.contains(key.as_str());
+                        reads.entry(key).or_default().push((t, list, external));
+                    }
+                    Mop::Write(_, _) => {
+                        return Err(anyhow::anyhow!("write in a list-append history"))
+                    }
+                }
+            }
+        }
+
+        for (key, reads) in reads {
+            let Some((longest_by, longest, _)) = reads.iter().max_by_key(|(_, list, _)| list.len())
+            else {
+                continue;
+            };
+            for pair in longest.windows(2) {
+                if let (Some(&from), Some(&to)) = (
+                    self.writers.get(&(key.to_string(), pair[0].clone())),
+                    self.writers.get(&(key.to_string(), pair[1].clone())),
+                ) {
+                    self.depend(from, to, Dependency::Ww, key);
+                }
+            }
+
+            let mut seen = HashSet::new();
+            for (t, list, external) in &reads {
+                if !longest.starts_with(list) {
+                    self.anomaly(
+                        AnomalyKind::IncompatibleOrder,
+                        &[*t, *longest_by],
+                        format!(
+                            "T0 read key {} as [{}] and T1 as [{}]",
+                            key,
+                            list.join(", "),
+                            longest.join(", ")
+                        ),
+                    );
+                    continue;
+                }
+                for value in list {
+                    if seen.insert(value) {
+                        self.written(*t, key, value);
+                    }
+                }
+                if !external {
+                    continue;
+                }
+                if let Some(last) = list.last() {
+                    self.read(*t, key, last);
+                }
+                if let Some(next) = longest.get(list.len()) {
+                    if let Some(&writer) = self.writers.get(&(key.to_string(), next.clone())) {
+                        self.depend(*t, writer, Dependency::Rw, key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rw_register(&mut self) {
+        for (t, txn) in self.txns.iter().enumerate() {
+            let mut last: HashMap<&str, &str> = HashMap::new();
+            for mop in &txn.mops {
+                let Mop::Write(key, value) = mop else {
+                    continue;
+                };
+                if let Some(previous) = last.insert(key, value) {
+                    self.intermediate
+                        .insert((key.clone(), previous.to_string()), t);
+                }
+            }
+            for (key, value) in last {
+                self.wrote(t, key, value);
+            }
+        }
+
+        // The first read of every key a transaction did not write first.
+        let mut reads = Vec::new();
+        let mut readers: HashMap<Version, Vec<usize>> = HashMap::new();
+        // Versions installed right after another one.
+        let mut successors = Vec::new();
+        for (t, txn) in self.txns.iter().enumerate() {
+            let mut observed: HashMap<&str, String> = HashMap::new();
+            let mut external: HashMap<&str, String> = HashMap::new();
+            for mop in &txn.mops {
+                match mop {
+                    Mop::Read(key, value) => {
+                        let value = value.to_string();
+                        match observed.get(key.as_str()) {
+                            Some(expected) if *expected != value => {
+                                let explanation = format!(
+                                    "T0 read key {} as {} after it was {}",
+                                    key, value, expected
+                                );
+                                self.anomaly(AnomalyKind::Internal, &[t], explanation);
+                            }
+                            Some(_) => {}
+                            None => {
+                                reads.push((t, key.as_str(), value.clone()));
+                                readers
+                                    .entry((key.clone(), value.clone()))
+                                    .or_default()
+                                    .push(t);
+                                external.insert(key, value.clone());
+                                observed.insert(key, value);
+                            }
+                        }
+                    }
+                    Mop::Write(key, value) => {
+                        observed.insert(key, value.clone());
+                    }
+                    Mop::Append(_, _) => {}
+                }
+            }
+            for mop in &txn.mops {
+                if let Mop::Write(key, _) = mop {
+                    if let Some(read) = external.remove(key.as_str()) {
+                        successors.push((t, key.as_str(), read));
+                    }
+                }
+            }
+        }
+
+        for (t, key, value) in reads {
+            if value != "null" {
+                self.written(t, key, &value);
+                self.read(t, key, &value);
+            }
+        }
+        // Every key starts out missing, so whoever read it that way comes
+        // before every writer.
+        let mut versions: Vec<(Version, usize)> =
+            self.writers.iter().map(|(v, t)| (v.clone(), *t)).collect();
+        versions.sort();
+        for ((key, _), writer) in versions {
+            for reader in readers
+                .get(&(key.clone(), "null".to_string()))
+                .into_iter()
+                .flatten()
+            {
+                self.depend(*reader, writer, Dependency::Rw, &key);
+            }
+        }
+        for (t, key, read) in successors {
+            let version = (key.to_string(), read);
+            if let Some(&writer) = self.writers.get(&version) {
+                self.depend(writer, t, Dependency::Ww, key);
+            }
+            for reader in readers.get(&version).into_iter().flatten() {
+                self.depend(*reader, t, Dependency::Rw, key);
+            }
+        }
+    }
+
+    /// Records that `t` installed `value`, if it did not fail.
+    fn wrote(&mut self, t: usize, key: &str, value: &str) {
+        let version = (key.to_string(), value.to_string());
+        if self.txns[t].kind == EventKind::Fail {
+            self.failed.insert(version, t);
+        } else {
+            self.writers.insert(version, t);
+        }
+    }
+
+    /// Checks that `value`, read by `t`, was ever installed.
+    fn written(&mut self, t: usize, key: &str, value: &str) {
+        let version = (key.to_string(), value.to_string());
+        if self.writers.contains_key(&version) || self.intermediate.contains_key(&version) {
+            return;
+        }
+        match self.failed.get(&version) {
+            Some(&writer) => self.anomaly(
+                AnomalyKind::G1a,
+                &[writer, t],
+                format!(
+                    "T1 read {} of key {}, written by T0, which failed",
+                    value, key
+                ),
+            ),
+            None => self.anomaly(
+                AnomalyKind::Garbage,
+                &[t],
+                format!("T0 read {} of key {}, which nothing wrote", value, key),
+            ),
+        }
+    }
+
+    /// Records that `t` read `value` as the latest version of `key`.
+    fn read(&mut self, t: usize, key: &str, value: &str) {
+        let version = (key.to_string(), value.to_string());
+        if let Some(&writer) = self.intermediate.get(&version) {
+            if writer != t {
+                self.anomaly(
+                    AnomalyKind::G1b,
+                    &[writer, t],
+                    format!(
+                        "T1 read {} of key {}, which T0 overwrote itself",
+                        value, key
+                    ),
+                );
+            }
+        }
+        if let Some(&writer) = self.writers.get(&version) {
+            self.depend(writer, t, Dependency::Wr, key);
+        }
+    }
+
+    fn depend(&mut self, from: usize, to: usize, dependency: Dependency, key: &str) {
+        if from != to {
+            self.edges[from]
+                .entry(to)
+                .or_default()
+                .entry(dependency)
+                .or_insert_with(|| key.to_string());
+        }
+    }
+
+    fn anomaly(&mut self, kind: AnomalyKind, txns: &[usize], explanation: String) {
+        self.anomalies.push(Anomaly {
+            kind,
+            explanation,
+            operations: txns
+                .iter()
+                .map(|t| self.operations[self.txns[*t].operation].clone())
+                .collect(),
+        });
+    }
+
+    /// Looks for one cycle of every kind. G2 is only looked for without a
+    /// G-single, which already rules out everything it would.
+    fn cycles(&mut self) {
+        let component = self.components();
+        let ww = Dependency::Ww.bit();
+        let wr = Dependency::Wr.bit();
+        let rw = Dependency::Rw.bit();
+        let searches = [
+            (AnomalyKind::G0, Dependency::Ww, ww),
+            (AnomalyKind::G1c, Dependency::Wr, ww | wr),
+            (AnomalyKind::GSingle, Dependency::Rw, ww | wr),
+            (AnomalyKind::G2, Dependency::Rw, ww | wr | rw),
+        ];
+        for (kind, first, allowed) in searches {
+            if kind == AnomalyKind::G2
+                && self
+                    .anomalies
+                    .iter()
+                    .any(|a| a.kind == AnomalyKind::GSingle)
+            {
+                continue;
+            }
+            let Some(cycle) = self.cycle(&component, first, allowed) else {
+                continue;
+            };
+            let mut explanation = String::new();
+            for (i, (_, dependency, key)) in cycle.iter().enumerate() {
+                explanation += &format!("T{} -{} {}-> ", i, dependency, key);
+            }
+            explanation += "T0";
+            let txns: Vec<usize> = cycle.iter().map(|(t, _, _)| *t).collect();
+            self.anomaly(kind, &txns, explanation);
+        }
+    }
+
+    /// A cycle that starts with a `first` dependency and goes on with
+    /// `allowed` ones, as every transaction and the dependency on the next.
+    fn cycle(
+        &self,
+        component: &[usize],
+        first: Dependency,
+        allowed: u8,
+    ) -> Option<Vec<(usize, Dependency, String)>> {
+        for (from, edges) in self.edges.iter().enumerate() {
+            for (to, dependencies) in edges {
+                let Some(key) = dependencies.get(&first) else {
+                    continue;
+                };
+                if component[from] != component[*to] {
+                    continue;
+                }
+                if let Some(path) = self.path(component, *to, from, allowed) {
+                    let mut cycle = vec![(from, first, key.clone())];
+                    cycle.extend(path);
+                    return Some(cycle);
+                }
+            }
+        }
+        None
+    }
+
+    /// The shortest path of `allowed` dependencies, found breadth first.
+    fn path(
+        &self,
+        component: &[usize],
+        from: usize,
+        to: usize,
+        allowed: u8,
+    ) -> Option<Vec<(usize, Dependency, String)>> {
+        let mut previous: HashMap<usize, (usize, Dependency, &String)> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(t) = queue.pop_front() {
+            if t == to {
+                let mut path = Vec::new();
+                let mut t = to;
+                while t != from {
+                    let (before, dependency, key) = previous[&t];
+                    path.push((before, dependency, key.clone()));
+                    t = before;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (next, dependencies) in &self.edges[t] {
+                if component[*next] != component[from] || *next == from {
+                    continue;
+                }
+                let Some((dependency, key)) = dependencies
+                    .iter()
+                    .find(|(dependency, _)| dependency.bit() & allowed != 0)
+                else {
+                    continue;
+                };
+                if !previous.contains_key(next) {
+                    previous.insert(*next, (t, *dependency, key));
+                    queue.push_back(*next);
+                }
+            }
+        }
+        None
+    }
+
+    /// The strongly connected components of the dependency graph, by
+    /// Tarjan's algorithm without recursion.
+    fn components(&self) -> Vec<usize> {
+        const UNSEEN: usize = usize::MAX;
+        let successors: Vec<Vec<usize>> = self
+            .edges
+            .iter()
+            .map(|edges| edges.keys().copied().collect())
+            .collect();
+        let n = successors.len();
+        let mut index = vec![UNSEEN; n];
+        let mut low = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut component = vec![UNSEEN; n];
+        let mut stack = Vec::new();
+        let mut next_index = 0;
+        let mut components = 0;
+        for root in 0..n {
+            if index[root] != UNSEEN {
+                continue;
+            }
+            index[root] = next_index;
+            low[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+            let mut calls = vec![(root, 0)];
+            while let Some((v, i)) = calls.last_mut() {
+                let v = *v;
+                if let Some(&w) = successors[v].get(*i) {
+                    *i += 1;
+                    if index[w] == UNSEEN {
+                        index[w] = next_index;
+                        low[w] = next_index;
+                        next_index += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        calls.push((w, 0));
+                    } else if on_stack[w] {
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+                calls.pop();
+                if let Some(&(parent, _)) = calls.last() {
+                    low[parent] = low[parent].min(low[v]);
+                }
+                if low[v] == index[v] {
+                    while let Some(w) = stack.pop() {
+                        on_stack[w] = false;
+                        component[w] = components;
+                        if w == v {
+                            break;
+                        }
+                    }
+                    components += 1;
+                }
+            }
+        }
+        component
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Runs a transaction that completes before the next one starts. The
+    /// request is `txn` with the values of the reads left out.
+    fn run(history: &mut History, kind: EventKind, txn: Value) {
+        let id = history.events.len();
+        let request: Vec<Value> = txn
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|mop| match mop[0].as_str() {
+                Some("r") => json!(["r", mop[1], null]),
+                _ => mop.clone(),
+            })
+            .collect();
+        history.push(
+            EventKind::Invoke,
+            "c1".to_string(),
+            id,
+            json!({"type": "txn", "txn": request}),
+        );
+        let reply = match kind {
+            EventKind::Ok => json!({"type": "txn_ok", "txn": txn}),
+            _ => json!({"type": "error", "code": 30, "text": ""}),
+        };
+        history.push(kind, "c1".to_string(), id, reply);
+    }
+
+    fn ok(history: &mut History, txn: Value) {
+        run(history, EventKind::Ok, txn);
+    }
+
+    fn fail(history: &mut History, txn: Value) {
+        run(history, EventKind::Fail, txn);
+    }
+
+    fn kinds(report: &Report) -> Vec<AnomalyKind> {
+        report.anomalies.iter().map(|a| a.kind).collect()
+    }
+
+    #[test]
+    fn serial_appends_are_valid() {
+        let mut h = History::default();
+        ok(&mut h, json!([["append", "x", 1], ["r", "y", null]]));
+        ok(&mut h, json!([["r", "x", [1]], ["append", "x", 2]]));
+        ok(&mut h, json!([["r", "x", [1, 2]], ["append", "y", 3]]));
+        ok(&mut h, json!([["r", "y", [3]]]));
+        let report = check(&h, Workload::ListAppend).unwrap();
+        assert!(report.is_valid(), "{}", report);
+    }
+
+    #[test]
+    fn write_cycles_are_g0() {
+        let mut h = History::default();
+        ok(&mut h, json!([["append", "x", 1], ["append", "y", 1]]));
+        ok(&mut h, json!([["append", "x", 2], ["append", "y", 2]]));
+        ok(&mut h, json!([["r", "x", [1, 2]], ["r", "y", [2, 1]]]));
+        let report = check(&h, Workload::ListAppend).unwrap();
+        assert_eq!(kinds(&report), [AnomalyKind::G0]);
+        assert_eq!(report.violated(), LEVELS);
+    }
+
+    #[test]
+    fn reading_each_others_writes_is_g1c() {
+        let mut h = History::default();
+        ok(&mut h, json!([["append", "x", 1], ["r", "y", [1]]]));
+        ok(&mut h, json!([["append", "y", 1], ["r", "x", [1]]]));
+        let report = check(&h, Workload::ListAppend).unwrap();
+        assert_eq!(kinds(&report), [AnomalyKind::G1c]);
+        assert_eq!(report.violated()[0], Level::ReadCommitted);
+    }
+
+    #[test]
+    fn read_skew_is_g_single() {
+        let mut h = History::default();
+        ok(&mut h, json!([["append", "x", 1], ["append", "y", 1]]));
+        ok(&mut h, json!([["r", "x", null], ["r", "y", [1]]]));
+        ok(&mut h, json!([["r", "x", [1]]]));
+        let report = check(&h, Workload::ListAppend).unwrap();
+        assert_eq!(kinds(&report), [AnomalyKind::GSingle]);
+        assert_eq!(
+            report.violated(),
+            [Level::SnapshotIsolation, Level::Serializable]
+        );
+    }
+
+    #[test]
+    fn write_skew_is_g2() {
+        let mut h = History::default();
+        ok(&mut h, json!([["r", 1, null], ["w", 2, 1]]));
+        ok(&mut h, json!([["r", 2, null], ["w", 1, 2]]));
+        ok(&mut h, json!([["r", 1, 2], ["r", 2, 1]]));
+        let report = check(&h, Workload::RwRegister).unwrap();
+        assert_eq!(kinds(&report), [AnomalyKind::G2]);
+        assert_eq!(report.violated(), [Level::Serializable]);
+    }
+
+    #[test]
+    fn reads_of_failed_and_intermediate_writes() {
+        let mut h = History::default();
+        fail(&mut h, json!([["w", 1, 1]]));
+        ok(&mut h, json!([["w", 2, 1], ["w", 2, 2]]));
+        ok(&mut h, json!([["r", 1, 1], ["r", 2, 1], ["r", 3, 3]]));
+        let report = check(&h, Workload::RwRegister).unwrap();
+        assert_eq!(
+            kinds(&report),
+            [AnomalyKind::G1a, AnomalyKind::G1b, AnomalyKind::Garbage]
+        );
+    }
+}
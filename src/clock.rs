@@ -0,0 +1,45 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Where a node reads the time from.
+///
+/// Timers, rpc deadlines and nodes all go through the clock of their
+/// `NodeContext` instead of `Instant::now`, so the simulator can run them on
+/// virtual time. Cloning is cheap and every clone shows the same time.
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    /// The system's monotonic clock.
+    #[default]
+    System,
+    /// A clock that stands still until it is advanced.
+    Virtual(Arc<Mutex<Instant>>),
+}
+
+impl Clock {
+    /// A virtual clock showing `start`.
+    pub fn starting_at(start: Instant) -> Self {
+        Clock::Virtual(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Virtual(now) => *now.lock().unwrap(),
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        matches!(self, Clock::Virtual(_))
+    }
+
+    /// Moves a virtual clock forward to `to`. It never goes back, and the
+    /// system clock is left alone.
+    pub fn advance_to(&self, to: Instant) {
+        if let Clock::Virtual(now) = self {
+            let mut now = now.lock().unwrap();
+            *now = (*now).max(to);
+        }
+    }
+}
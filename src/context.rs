@@ -0,0 +1,196 @@
+use std::{collections::HashMap, fmt::Debug, io::Write, time::Instant};
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    clock::Clock,
+    error::{Error, ErrorCode},
+    rpc::{client::Rpc, retry::RetryPolicy},
+    timers::Timers,
+    utils::await_event::ResponseHandle,
+    Init, Message,
+};
+
+type Callback<T> =
+    Box<dyn FnOnce(&mut NodeContext<T>, anyhow::Result<Message<Value>>) -> anyhow::Result<()>>;
+
+/// Everything a node needs to talk to the cluster, handed to every
+/// `Node` method by `main_loop`.
+///
+/// Every message sent through the context gets the next `msg_id` of the
+/// node, so ids never repeat, replies included.
+pub struct NodeContext<T = ()> {
+    pub node_id: String,
+    /// Every node of the cluster, `node_id` included, in the order `init`
+    /// listed them.
+    pub node_ids: Vec<String>,
+    pub timers: Timers<T>,
+    pub rpc: Rpc,
+    pub clock: Clock,
+    rng: StdRng,
+    msg_id: usize,
+    out: Box<dyn Write>,
+    callbacks: HashMap<usize, Callback<T>>,
+}
+
+impl<T> NodeContext<T> {
+    pub fn new(init: Init, out: Box<dyn Write>) -> Self {
+        NodeContext::with_clock(init, out, Clock::System, StdRng::from_entropy())
+    }
+
+    /// A context whose time comes from `clock` and whose randomness comes
+    /// from `rng`, for running a node deterministically.
+    pub fn with_clock(init: Init, out: Box<dyn Write>, clock: Clock, rng: StdRng) -> Self {
+        NodeContext {
+            node_id: init.node_id,
+            node_ids: init.node_ids,
+            timers: Timers::new(clock.clone()),
+            rpc: Rpc::new(clock.clone()),
+            clock,
+            rng,
+            msg_id: 0,
+            out,
+            callbacks: HashMap::new(),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// The random numbers a node should use, seeded by the simulator when
+    /// it runs on one.
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// Every node of the cluster except this one.
+    pub fn peers(&self) -> impl Iterator<Item = &String> {
+        self.node_ids.iter().filter(move |id| **id != self.node_id)
+    }
+
+    pub fn next_msg_id(&mut self) -> usize {
+        self.msg_id += 1;
+        self.msg_id
+    }
+
+    /// Builds a message from this node to `dest` with a fresh `msg_id`.
+    pub fn message<L: Serialize + Debug>(
+        &mut self,
+        dest: impl Into<String>,
+        payload: L,
+    ) -> Message<L> {
+        Message::new(self.node_id.clone(), dest.into(), payload, &mut self.msg_id)
+    }
+
+    pub fn send<L: Serialize + Debug>(
+        &mut self,
+        dest: impl Into<String>,
+        payload: L,
+    ) -> anyhow::Result<()> {
+        let message = self.message(dest, payload);
+        message.send(&mut self.out)
+    }
+
+    pub fn reply<Q, L>(&mut self, request: &Message<Q>, payload: L) -> anyhow::Result<()>
+    where
+        Q: Serialize + Debug,
+        L: Serialize + Debug,
+    {
+        request.reply(payload, &mut self.msg_id).send(&mut self.out)
+    }
+
+    pub fn reply_error<Q: Serialize + Debug>(
+        &mut self,
+        request: &Message<Q>,
+        code: ErrorCode,
+        text: impl Into<String>,
+    ) -> anyhow::Result<()> {
+        self.reply(request, Error::new(code, text))
+    }
+
+    /// Where messages are written to, for sending a `Message` built by hand.
+    pub fn out(&mut self) -> &mut dyn Write {
+        &mut self.out
+    }
+
+    /// Sends `payload` to `dest` and returns a handle that resolves with the
+    /// reply. See `Rpc::call`.
+    pub fn call<Q, R>(
+        &mut self,
+        dest: impl Into<String>,
+        payload: Q,
+        policy: RetryPolicy,
+    ) -> anyhow::Result<ResponseHandle<R>>
+    where
+        Q: Serialize + Debug,
+        R: DeserializeOwned + Serialize + Debug,
+    {
+        let message = self.message(dest, payload);
+        self.rpc.call(&message, policy, &mut self.out)
+    }
+
+    /// Sends `payload` to `dest` and runs `callback` once the reply arrives
+    /// or `policy` gives up. An `error` reply is passed on as the
+    /// `error::Error` it carries. The reply never reaches `Node::handle`.
+    pub fn call_with<Q, R, F>(
+        &mut self,
+        dest: impl Into<String>,
+        payload: Q,
+        policy: RetryPolicy,
+        callback: F,
+    ) -> anyhow::Result<()>
+    where
+        Q: Serialize + Debug,
+        R: DeserializeOwned + Serialize + Debug,
+        F: FnOnce(&mut NodeContext<T>, anyhow::Result<Message<R>>) -> anyhow::Result<()> + 'static,
+    {
+        let message = self.message(dest, payload);
+        let msg_id = self.rpc.expect_callback(&message, policy)?;
+        self.callbacks.insert(
+            msg_id,
+            Box::new(move |ctx, reply| callback(ctx, reply.and_then(Message::decode_reply))),
+        );
+        message.send(&mut self.out)
+    }
+
+    /// The earliest point at which a timer fires or an rpc request has to be
+    /// resent or failed.
+    pub fn next_deadline(&self) -> Option<Instant>
+    where
+        T: Clone + PartialEq,
+    {
+        self.rpc
+            .next_deadline()
+            .into_iter()
+            .chain(self.timers.next_deadline())
+            .min()
+    }
+
+    /// Resends rpc requests whose backoff is over and runs the callbacks of
+    /// the ones that ran out of retries.
+    pub fn expire(&mut self, now: Instant) -> anyhow::Result<()> {
+        for (msg_id, error) in self.rpc.expire(now, &mut self.out, &mut self.rng)? {
+            if let Some(callback) = self.callbacks.remove(&msg_id) {
+                callback(self, Err(error.into()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the callback waiting for `reply`.
+    pub fn complete(&mut self, reply: Message<Value>) -> anyhow::Result<()> {
+        let Some(msg_id) = reply.body.in_reply_to else {
+            return Ok(());
+        };
+        if !self.rpc.take_callback(msg_id) {
+            return Ok(());
+        }
+        match self.callbacks.remove(&msg_id) {
+            Some(callback) => callback(self, Ok(reply)),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+/// A state-based CRDT: replicas converge by merging each other's states.
+///
+/// `merge` is the join of a semilattice, so it has to be commutative,
+/// associative and idempotent. Every mutator changes the state in place
+/// and returns its delta, a state of the same type holding just the change,
+/// which can be merged into other replicas instead of the whole state.
+pub trait Crdt: Clone + Default + PartialEq {
+    fn merge(&mut self, other: &Self);
+}
+
+/// A counter that only grows, as a total per node.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn increment(&mut self, node: &str, by: u64) -> GCounter {
+        let count = self.counts.entry(node.to_string()).or_default();
+        *count += by;
+        GCounter {
+            counts: BTreeMap::from([(node.to_string(), *count)]),
+        }
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node, count) in &other.counts {
+            let ours = self.counts.entry(node.clone()).or_default();
+            *ours = (*ours).max(*count);
+        }
+    }
+}
+
+/// A counter that can go both ways, as two `GCounter`s of the increments
+/// and the decrements.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PNCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    pub fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+
+    pub fn add(&mut self, node: &str, delta: i64) -> PNCounter {
+        let mut change = PNCounter::default();
+        if delta >= 0 {
+            change.increments = self.increments.increment(node, delta as u64);
+        } else {
+            change.decrements = self.decrements.increment(node, delta.unsigned_abs());
+        }
+        change
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+}
+
+/// A set that only grows.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GSet<T: Ord> {
+    items: BTreeSet<T>,
+}
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        GSet {
+            items: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> GSet<T> {
+    pub fn contains(&self, item: &T) -> bool {
+        self.items.contains(item)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The delta is empty if `item` was already there.
+    pub fn insert(&mut self, item: T) -> GSet<T> {
+        let mut change = GSet::default();
+        if self.items.insert(item.clone()) {
+            change.items.insert(item);
+        }
+        change
+    }
+}
+
+impl<T: Ord + Clone> Crdt for GSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.items.extend(other.items.iter().cloned());
+    }
+}
+
+/// Identifies a single insert into an `ORSet`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Dot {
+    pub node: String,
+    pub counter: u64,
+}
+
+/// An observed-remove set: every insert is tagged with a unique `Dot`, and a
+/// remove only drops the dots it has seen, so an insert concurrent with a
+/// remove wins. Removed dots are kept as tombstones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ORSet<T: Ord> {
+    inserted: BTreeMap<T, BTreeSet<Dot>>,
+    removed: BTreeSet<Dot>,
+    /// The highest counter every node used for a dot.
+    clock: BTreeMap<String, u64>,
+}
+
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        ORSet {
+            inserted: BTreeMap::new(),
+            removed: BTreeSet::new(),
+            clock: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    pub fn contains(&self, item: &T) -> bool {
+        self.inserted
+            .get(item)
+            .is_some_and(|dots| dots.iter().any(|d| !self.removed.contains(d)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.inserted
+            .iter()
+            .filter(|(_, dots)| dots.iter().any(|d| !self.removed.contains(d)))
+            .map(|(item, _)| item)
+    }
+
+    pub fn insert(&mut self, node: &str, item: T) -> ORSet<T> {
+        let counter = self.clock.entry(node.to_string()).or_default();
+        *counter += 1;
+        let dot = Dot {
+            node: node.to_string(),
+            counter: *counter,
+        };
+        self.inserted
+            .entry(item.clone())
+            .or_default()
+            .insert(dot.clone());
+
+        let mut change = ORSet::default();
+        change.clock.insert(node.to_string(), dot.counter);
+        change.inserted.insert(item, BTreeSet::from([dot]));
+        change
+    }
+
+    pub fn remove(&mut self, item: &T) -> ORSet<T> {
+        let mut change = ORSet::default();
+        if let Some(dots) = self.inserted.get(item) {
+            change.removed = dots.difference(&self.removed).cloned().collect();
+            self.removed.extend(change.removed.iter().cloned());
+        }
+        change
+    }
+}
+
+impl<T: Ord + Clone> Crdt for ORSet<T> {
+    fn merge(&mut self, other: &Self) {
+        for (item, dots) in &other.inserted {
+            self.inserted
+                .entry(item.clone())
+                .or_default()
+                .extend(dots.iter().cloned());
+        }
+        self.removed.extend(other.removed.iter().cloned());
+        for (node, counter) in &other.clock {
+            let ours = self.clock.entry(node.clone()).or_default();
+            *ours = (*ours).max(*counter);
+        }
+    }
+}
+
+/// Orders the writes of an `LWWRegister`, the node id breaking ties.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub counter: u64,
+    pub node: String,
+}
+
+/// A register where the write with the highest `Timestamp` wins. A write
+/// always gets a higher timestamp than the one it replaces, so a replica
+/// never goes back to an older value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LWWRegister<T> {
+    entry: Option<(Timestamp, T)>,
+}
+
+impl<T> Default for LWWRegister<T> {
+    fn default() -> Self {
+        LWWRegister { entry: None }
+    }
+}
+
+impl<T: Clone> LWWRegister<T> {
+    pub fn get(&self) -> Option<&T> {
+        self.entry.as_ref().map(|(_, value)| value)
+    }
+
+    /// The whole register is its own delta.
+    pub fn set(&mut self, node: &str, value: T) -> LWWRegister<T> {
+        let counter = self.entry.as_ref().map_or(0, |(ts, _)| ts.counter) + 1;
+        let timestamp = Timestamp {
+            counter,
+            node: node.to_string(),
+        };
+        self.entry = Some((timestamp, value));
+        self.clone()
+    }
+}
+
+impl<T: Clone + PartialEq> Crdt for LWWRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        let newer = match (&self.entry, &other.entry) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some((ours, _)), Some((theirs, _))) => theirs > ours,
+        };
+        if newer {
+            self.entry = other.entry.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged<C: Crdt>(a: &C, b: &C) -> C {
+        let mut a = a.clone();
+        a.merge(b);
+        a
+    }
+
+    /// Checks commutativity, associativity and idempotence of `merge` on
+    /// every pair and triple of `states`.
+    fn assert_semilattice<C: Crdt + std::fmt::Debug>(states: &[C]) {
+        for a in states {
+            assert_eq!(merged(a, a), *a);
+            for b in states {
+                assert_eq!(merged(a, b), merged(b, a));
+                for c in states {
+                    assert_eq!(merged(&merged(a, b), c), merged(a, &merged(b, c)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn g_counter() {
+        let mut a = GCounter::default();
+        let mut b = GCounter::default();
+        a.increment("n1", 2);
+        b.increment("n2", 3);
+        let mut c = a.clone();
+        c.increment("n1", 4);
+        assert_semilattice(&[GCounter::default(), a.clone(), b.clone(), c.clone()]);
+        assert_eq!(merged(&merged(&a, &b), &c).value(), 9);
+    }
+
+    #[test]
+    fn pn_counter() {
+        let mut a = PNCounter::default();
+        let mut b = PNCounter::default();
+        a.add("n1", 5);
+        b.add("n2", -7);
+        let mut c = b.clone();
+        c.add("n2", 3);
+        assert_semilattice(&[PNCounter::default(), a.clone(), b.clone(), c.clone()]);
+        assert_eq!(merged(&a, &c).value(), 1);
+    }
+
+    #[test]
+    fn g_set() {
+        let mut a = GSet::default();
+        let mut b = GSet::default();
+        a.insert(1);
+        b.insert(2);
+        let delta = b.insert(3);
+        assert!(b.insert(3).is_empty());
+        assert_semilattice(&[GSet::default(), a.clone(), b.clone(), delta.clone()]);
+        assert_eq!(
+            merged(&a, &delta).iter().copied().collect::<Vec<_>>(),
+            [1, 3]
+        );
+    }
+
+    #[test]
+    fn or_set_add_wins() {
+        let mut a = ORSet::default();
+        a.insert("n1", "x");
+        let mut b = a.clone();
+        // Removing what was observed while another insert happens
+        // concurrently keeps the item.
+        b.remove(&"x");
+        a.insert("n1", "x");
+        let mut c = ORSet::default();
+        c.insert("n2", "y");
+        assert_semilattice(&[ORSet::default(), a.clone(), b.clone(), c.clone()]);
+        assert!(merged(&a, &b).contains(&"x"));
+        assert!(!merged(&b, &c).contains(&"x"));
+        assert!(merged(&b, &c).contains(&"y"));
+    }
+
+    #[test]
+    fn lww_register() {
+        let mut a = LWWRegister::default();
+        a.set("n1", 1);
+        let mut b = a.clone();
+        b.set("n2", 2);
+        let mut c = LWWRegister::default();
+        c.set("n3", 3);
+        assert_semilattice(&[LWWRegister::default(), a.clone(), b.clone(), c.clone()]);
+        assert_eq!(merged(&a, &b).get(), Some(&2));
+        assert_eq!(merged(&b, &c).get(), Some(&2));
+        // Concurrent writes with the same counter are ordered by node id.
+        assert_eq!(merged(&a, &c).get(), Some(&3));
+    }
+}
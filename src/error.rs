@@ -0,0 +1,197 @@
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{kv::KvError, rpc::client::RpcError};
+
+/// The error codes understood by the maelstrom test harness.
+///
+/// Codes below 1000 are reserved by maelstrom, anything else a workload
+/// defines for itself ends up in `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    Other(u32),
+}
+
+impl ErrorCode {
+    pub fn code(self) -> u32 {
+        match self {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Other(code) => code,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Other(code),
+        }
+    }
+
+    /// Whether the failed operation is known not to have taken place.
+    /// `timeout` and `crash` leave that open, as may custom codes.
+    pub fn is_definite(self) -> bool {
+        !matches!(
+            self,
+            ErrorCode::Timeout | ErrorCode::Crash | ErrorCode::Other(_)
+        )
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u32::deserialize(deserializer).map(ErrorCode::from_code)
+    }
+}
+
+/// The body of an `error` message.
+///
+/// It doubles as a Rust error: a handler that returns it has it sent back to
+/// the client as is, and `Rpc` fails a request with it when the reply is an
+/// error.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename = "error")]
+pub struct Error {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Error {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "error {:?} ({}): {}",
+            self.code,
+            self.code.code(),
+            self.text
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<anyhow::Error> for Error {
+    /// Keeps an `Error` returned through anyhow, maps a `KvError` to its
+    /// code and an `RpcError` to `timeout`, anything else is reported as a
+    /// crash.
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<Error>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        let error = match error.downcast::<KvError>() {
+            Ok(error) => return error.into(),
+            Err(error) => error,
+        };
+        match error.downcast::<RpcError>() {
+            Ok(error) => error.into(),
+            Err(error) => Error::new(ErrorCode::Crash, format!("{:#}", error)),
+        }
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(error: RpcError) -> Self {
+        match error {
+            RpcError::Timeout { .. } => Error::new(ErrorCode::Timeout, error.to_string()),
+        }
+    }
+}
+
+impl From<KvError> for Error {
+    fn from(error: KvError) -> Self {
+        match error {
+            KvError::KeyDoesNotExist => Error::new(ErrorCode::KeyDoesNotExist, error.to_string()),
+            KvError::PreconditionFailed => {
+                Error::new(ErrorCode::PreconditionFailed, error.to_string())
+            }
+            KvError::Other(error) => error.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kv_errors_keep_their_code() {
+        let error = Error::from(anyhow::Error::from(KvError::PreconditionFailed));
+        assert_eq!(error.code, ErrorCode::PreconditionFailed);
+
+        let error = Error::from(anyhow::Error::from(KvError::KeyDoesNotExist));
+        assert_eq!(error.code, ErrorCode::KeyDoesNotExist);
+
+        let reply = Error::new(ErrorCode::TemporarilyUnavailable, "no leader");
+        let error = Error::from(anyhow::Error::from(KvError::Other(reply.clone().into())));
+        assert_eq!(error, reply);
+    }
+
+    #[test]
+    fn rpc_timeouts_are_timeouts() {
+        let timeout = RpcError::Timeout {
+            msg_id: 4,
+            attempts: 2,
+        };
+        let error = Error::from(anyhow::Error::from(timeout.clone()));
+        assert_eq!(error.code, ErrorCode::Timeout);
+        assert!(!error.code.is_definite());
+
+        // A kv call that timed out keeps the timeout as well.
+        let error = Error::from(anyhow::Error::from(KvError::Other(timeout.into())));
+        assert_eq!(error.code, ErrorCode::Timeout);
+    }
+
+    #[test]
+    fn other_errors_are_crashes() {
+        let error = Error::from(anyhow::anyhow!("boom"));
+        assert_eq!(error.code, ErrorCode::Crash);
+        assert_eq!(error.text, "boom");
+    }
+}
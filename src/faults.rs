@@ -0,0 +1,502 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{self, Display},
+    str::FromStr,
+    time::Duration,
+};
+
+use rand::{seq::SliceRandom, Rng};
+
+/// How long a message takes to arrive.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Latency {
+    Constant(Duration),
+    /// Anything in `[min, max)`, all equally likely.
+    Uniform(Duration, Duration),
+    /// Exponentially distributed around a mean, so most messages are quick
+    /// and a few are very late.
+    Exponential(Duration),
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Uniform(Duration::from_millis(1), Duration::from_millis(10))
+    }
+}
+
+impl Latency {
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match self {
+            Latency::Constant(latency) => *latency,
+            Latency::Uniform(min, max) if min >= max => *min,
+            Latency::Uniform(min, max) => rng.gen_range(*min..*max),
+            Latency::Exponential(mean) => {
+                let uniform: f64 = rng.gen_range(f64::EPSILON..1.0);
+                mean.mul_f64(-uniform.ln())
+            }
+        }
+    }
+}
+
+/// `5ms`, `1ms..10ms` or `exp 20ms`.
+impl FromStr for Latency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(mean) = s.strip_prefix("exp ") {
+            Ok(Latency::Exponential(parse_duration(mean)?))
+        } else if let Some((min, max)) = s.split_once("..") {
+            Ok(Latency::Uniform(parse_duration(min)?, parse_duration(max)?))
+        } else {
+            Ok(Latency::Constant(parse_duration(s)?))
+        }
+    }
+}
+
+/// Which nodes can still talk to each other.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Partition {
+    /// Nodes can only reach the nodes of their own group. A node in two
+    /// groups reaches both.
+    Groups(Vec<BTreeSet<String>>),
+    /// A random majority and the minority left over.
+    Halves,
+    /// Two random halves joined by one node that is in both.
+    Bridge,
+}
+
+impl Partition {
+    /// Turns `Halves` and `Bridge` into groups of `node_ids`.
+    pub fn resolve(&self, node_ids: &[String], rng: &mut impl Rng) -> Vec<BTreeSet<String>> {
+        let mut shuffled = node_ids.to_vec();
+        shuffled.shuffle(rng);
+        match self {
+            Partition::Groups(groups) => groups.clone(),
+            Partition::Halves => {
+                let minority = shuffled.split_off(shuffled.len() / 2 + 1);
+                vec![
+                    shuffled.into_iter().collect(),
+                    minority.into_iter().collect(),
+                ]
+            }
+            Partition::Bridge => {
+                let Some(bridge) = shuffled.pop() else {
+                    return Vec::new();
+                };
+                let mut other = shuffled.split_off(shuffled.len() / 2);
+                shuffled.push(bridge.clone());
+                other.push(bridge);
+                vec![shuffled.into_iter().collect(), other.into_iter().collect()]
+            }
+        }
+    }
+}
+
+/// `{n0,n1}|{n2}`, `halves` or `bridge`.
+impl FromStr for Partition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "halves" | "majority" => Ok(Partition::Halves),
+            "bridge" => Ok(Partition::Bridge),
+            groups => groups
+                .split('|')
+                .map(|group| {
+                    let nodes = group
+                        .trim()
+                        .strip_prefix('{')
+                        .and_then(|g| g.strip_suffix('}'))
+                        .ok_or_else(|| anyhow::anyhow!("expected {{node,...}}, got {:?}", group))?;
+                    Ok(nodes
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|n| !n.is_empty())
+                        .map(str::to_string)
+                        .collect())
+                })
+                .collect::<anyhow::Result<_>>()
+                .map(Partition::Groups),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    Partition(Partition),
+    /// Every message is lost with this probability.
+    Drop(f64),
+    /// Replaces the network's latency.
+    Latency(Latency),
+    /// Every message is delivered twice with this probability.
+    Duplicate(f64),
+    /// Every message is held back by up to the window with this
+    /// probability, so that later ones overtake it.
+    Reorder(f64, Duration),
+}
+
+impl FromStr for Fault {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (kind, args) = s.split_once(' ').unwrap_or((s, ""));
+        match kind {
+            "partition" => Ok(Fault::Partition(args.parse()?)),
+            "drop" => Ok(Fault::Drop(parse_probability(args)?)),
+            "latency" => Ok(Fault::Latency(args.parse()?)),
+            "duplicate" => Ok(Fault::Duplicate(parse_probability(args)?)),
+            "reorder" => {
+                let (probability, window) = args
+                    .trim()
+                    .split_once(' ')
+                    .ok_or_else(|| anyhow::anyhow!("expected reorder <probability> <window>"))?;
+                Ok(Fault::Reorder(
+                    parse_probability(probability)?,
+                    parse_duration(window)?,
+                ))
+            }
+            _ => Err(anyhow::anyhow!("unknown fault {:?}", kind)),
+        }
+    }
+}
+
+/// A fault that holds from `from` until `until`, forever if that is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheduled {
+    pub fault: Fault,
+    pub from: Duration,
+    pub until: Option<Duration>,
+}
+
+impl Scheduled {
+    pub fn is_active(&self, at: Duration) -> bool {
+        self.from <= at && self.until.is_none_or(|until| at < until)
+    }
+}
+
+/// `<fault> [from <time>] [to <time>]`, for example
+/// `partition {n0,n1}|{n2} from 2s to 5s`. Times may also be written
+/// `t=2s`.
+impl FromStr for Scheduled {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rest, until) = match s.rsplit_once(" to ") {
+            Some((rest, until)) => (rest, Some(parse_duration(until)?)),
+            None => (s, None),
+        };
+        let (fault, from) = match rest.rsplit_once(" from ") {
+            Some((fault, from)) => (fault, parse_duration(from)?),
+            None => (rest, Duration::ZERO),
+        };
+        Ok(Scheduled {
+            fault: fault.parse()?,
+            from,
+            until,
+        })
+    }
+}
+
+/// The faults of a run, each with the time span it holds for. Faults that
+/// overlap all apply, the one listed last picks the latency.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timeline {
+    pub faults: Vec<Scheduled>,
+}
+
+/// Scheduled faults separated by `;` or newlines, blank entries and
+/// `#` comments ignored.
+impl FromStr for Timeline {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let faults = s
+            .split(['\n', ';'])
+            .map(|entry| entry.split('#').next().unwrap_or("").trim())
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("in {:?}: {:#}", entry, e))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Timeline { faults })
+    }
+}
+
+/// What the network does to a message, see `Faults::fate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fate {
+    /// When each copy arrives, counted from when it was sent. Empty if the
+    /// message is lost.
+    pub delays: Vec<Duration>,
+}
+
+impl Display for Fate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.delays.as_slice() {
+            [] => write!(f, "dropped"),
+            delays => write!(f, "delivered after {:?}", delays),
+        }
+    }
+}
+
+/// Applies a `Timeline` to the messages of a cluster.
+///
+/// Only messages between two of `node_ids` are partitioned, dropped,
+/// duplicated or reordered, clients and services are always reached.
+/// Latency applies to every message.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    pub timeline: Timeline,
+    node_ids: Vec<String>,
+    /// The groups `Halves` and `Bridge` were resolved to, by their index in
+    /// the timeline, picked when they start.
+    partitions: HashMap<usize, Vec<BTreeSet<String>>>,
+}
+
+impl Faults {
+    pub fn new(timeline: Timeline, node_ids: Vec<String>) -> Self {
+        Faults {
+            timeline,
+            node_ids,
+            partitions: HashMap::new(),
+        }
+    }
+
+    /// Decides what happens to a message from `src` to `dest` sent at `at`,
+    /// drawing from `rng`. `latency` is used unless a fault replaces it.
+    pub fn fate(
+        &mut self,
+        at: Duration,
+        src: &str,
+        dest: &str,
+        latency: &Latency,
+        rng: &mut impl Rng,
+    ) -> Fate {
+        let active: Vec<usize> = (0..self.timeline.faults.len())
+            .filter(|i| self.timeline.faults[*i].is_active(at))
+            .collect();
+        let latency = active
+            .iter()
+            .rev()
+            .find_map(|i| match &self.timeline.faults[*i].fault {
+                Fault::Latency(latency) => Some(latency.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| latency.clone());
+
+        let between_nodes =
+            self.node_ids.iter().any(|n| n == src) && self.node_ids.iter().any(|n| n == dest);
+        if !between_nodes {
+            return Fate {
+                delays: vec![latency.sample(rng)],
+            };
+        }
+
+        let mut copies = 1;
+        let mut reorder = Vec::new();
+        for i in active {
+            match &self.timeline.faults[i].fault {
+                Fault::Partition(partition) => {
+                    let node_ids = &self.node_ids;
+                    let groups = self
+                        .partitions
+                        .entry(i)
+                        .or_insert_with(|| partition.resolve(node_ids, rng));
+                    if cut(groups, src, dest) {
+                        return Fate { delays: Vec::new() };
+                    }
+                }
+                Fault::Drop(probability) => {
+                    if rng.gen_bool(*probability) {
+                        return Fate { delays: Vec::new() };
+                    }
+                }
+                Fault::Duplicate(probability) => {
+                    if rng.gen_bool(*probability) {
+                        copies += 1;
+                    }
+                }
+                Fault::Reorder(probability, window) => reorder.push((*probability, *window)),
+                Fault::Latency(_) => {}
+            }
+        }
+
+        let delays = (0..copies)
+            .map(|_| {
+                let mut delay = latency.sample(rng);
+                for (probability, window) in &reorder {
+                    if rng.gen_bool(*probability) {
+                        delay += Latency::Uniform(Duration::ZERO, *window).sample(rng);
+                    }
+                }
+                delay
+            })
+            .collect();
+        Fate { delays }
+    }
+}
+
+/// Whether `groups` keep `src` from reaching `dest`: both are in some group,
+/// but in no group together.
+fn cut(groups: &[BTreeSet<String>], src: &str, dest: &str) -> bool {
+    let grouped = |node: &str| groups.iter().any(|g| g.contains(node));
+    grouped(src) && grouped(dest) && !groups.iter().any(|g| g.contains(src) && g.contains(dest))
+}
+
+/// `1.5s`, `200ms` or `50us`, optionally written as `t=1.5s`.
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim().trim_start_matches("t=");
+    let (number, unit) = s
+        .find(|c: char| c.is_ascii_alphabetic())
+        .map(|i| s.split_at(i))
+        .ok_or_else(|| anyhow::anyhow!("duration {:?} has no unit", s))?;
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("bad duration {:?}", s))?;
+    let seconds = match unit {
+        "s" => number,
+        "ms" => number / 1e3,
+        "us" => number / 1e6,
+        _ => return Err(anyhow::anyhow!("unknown unit in duration {:?}", s)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| anyhow::anyhow!("bad duration {:?}", s))
+}
+
+fn parse_probability(s: &str) -> anyhow::Result<f64> {
+    let probability: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("bad probability {:?}", s))?;
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(anyhow::anyhow!(
+            "probability {} is not in [0, 1]",
+            probability
+        ));
+    }
+    Ok(probability)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn nodes(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("n{}", i)).collect()
+    }
+
+    #[test]
+    fn parses_a_timeline() {
+        let timeline: Timeline = "partition {n0,n1}|{n2} from 2s to 5s; drop 0.1\n\
+                                  # noisy network\n\
+                                  latency exp 20ms from 500ms\n\
+                                  reorder 0.5 30ms to 1.5s"
+            .parse()
+            .unwrap();
+        let groups = Partition::Groups(vec![
+            BTreeSet::from(["n0".to_string(), "n1".to_string()]),
+            BTreeSet::from(["n2".to_string()]),
+        ]);
+        assert_eq!(
+            timeline.faults,
+            vec![
+                Scheduled {
+                    fault: Fault::Partition(groups),
+                    from: Duration::from_secs(2),
+                    until: Some(Duration::from_secs(5)),
+                },
+                Scheduled {
+                    fault: Fault::Drop(0.1),
+                    from: Duration::ZERO,
+                    until: None,
+                },
+                Scheduled {
+                    fault: Fault::Latency(Latency::Exponential(Duration::from_millis(20))),
+                    from: Duration::from_millis(500),
+                    until: None,
+                },
+                Scheduled {
+                    fault: Fault::Reorder(0.5, Duration::from_millis(30)),
+                    from: Duration::ZERO,
+                    until: Some(Duration::from_millis(1500)),
+                },
+            ]
+        );
+        assert!("drop 2".parse::<Timeline>().is_err());
+        assert!("flood 0.5".parse::<Timeline>().is_err());
+    }
+
+    #[test]
+    fn parses_times_written_with_t() {
+        let scheduled: Scheduled = "partition {n1,n2}|{n3} from t=2s to t=5s".parse().unwrap();
+        let groups = Partition::Groups(vec![
+            BTreeSet::from(["n1".to_string(), "n2".to_string()]),
+            BTreeSet::from(["n3".to_string()]),
+        ]);
+        assert_eq!(
+            scheduled,
+            Scheduled {
+                fault: Fault::Partition(groups),
+                from: Duration::from_secs(2),
+                until: Some(Duration::from_secs(5)),
+            }
+        );
+        assert!(parse_duration("t=").is_err());
+    }
+
+    #[test]
+    fn partitions_cut_only_across_groups_while_active() {
+        let timeline = "partition {n0,n1}|{n2} from 1s to 2s".parse().unwrap();
+        let mut faults = Faults::new(timeline, nodes(3));
+        let mut rng = StdRng::seed_from_u64(1);
+        let latency = Latency::Constant(Duration::from_millis(5));
+        let mut fate = |at: u64, src, dest| {
+            faults.fate(Duration::from_millis(at), src, dest, &latency, &mut rng)
+        };
+
+        assert_eq!(fate(1500, "n0", "n2").delays, []);
+        assert_eq!(fate(1500, "n2", "n1").delays, []);
+        assert_eq!(fate(1500, "n0", "n1").delays.len(), 1);
+        assert_eq!(fate(1500, "c1", "n2").delays.len(), 1);
+        assert_eq!(fate(500, "n0", "n2").delays.len(), 1);
+        assert_eq!(fate(2000, "n0", "n2").delays.len(), 1);
+    }
+
+    #[test]
+    fn bridges_connect_both_halves() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let groups = Partition::Bridge.resolve(&nodes(5), &mut rng);
+        let bridge: Vec<&String> = groups[0].intersection(&groups[1]).collect();
+        assert_eq!(bridge.len(), 1);
+        assert_eq!(groups[0].len() + groups[1].len(), 6);
+        for node in nodes(5) {
+            assert!(!cut(&groups, bridge[0], &node));
+        }
+
+        let halves = Partition::Halves.resolve(&nodes(5), &mut rng);
+        assert_eq!((halves[0].len(), halves[1].len()), (3, 2));
+    }
+
+    #[test]
+    fn drops_and_duplicates_follow_their_probability() {
+        let timeline = "drop 0.25; duplicate 0.5".parse().unwrap();
+        let mut faults = Faults::new(timeline, nodes(2));
+        let mut rng = StdRng::seed_from_u64(5);
+        let latency = Latency::default();
+        let mut copies = [0; 3];
+        for _ in 0..4000 {
+            let fate = faults.fate(Duration::ZERO, "n0", "n1", &latency, &mut rng);
+            copies[fate.delays.len()] += 1;
+        }
+        // 1/4 lost, 3/8 delivered once and 3/8 twice.
+        assert!((900..1100).contains(&copies[0]), "{:?}", copies);
+        assert!((1350..1650).contains(&copies[1]), "{:?}", copies);
+        assert!((1350..1650).contains(&copies[2]), "{:?}", copies);
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env,
+    time::Duration,
+};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::crdt::Crdt;
+
+/// The environment variable that overrides `GossipConfig::interval`, in
+/// milliseconds.
+pub const GOSSIP_INTERVAL_ENV: &str = "GOSSIP_INTERVAL_MS";
+/// The environment variable that overrides `GossipConfig::fanout`.
+pub const GOSSIP_FANOUT_ENV: &str = "GOSSIP_FANOUT";
+
+/// How often batches go out and to how many peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GossipConfig {
+    /// Values gathered during one interval go out as a single batch.
+    pub interval: Duration,
+    /// How many peers get a batch per interval, `None` for all of them.
+    pub fanout: Option<usize>,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        GossipConfig {
+            interval: Duration::from_millis(100),
+            fanout: None,
+        }
+    }
+}
+
+impl GossipConfig {
+    /// Reads `GOSSIP_INTERVAL_MS` and `GOSSIP_FANOUT`, falling back to the
+    /// defaults for whatever is not set.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_env_or(GossipConfig::default())
+    }
+
+    /// Like `from_env`, but falls back to `defaults`.
+    pub fn from_env_or(defaults: GossipConfig) -> anyhow::Result<Self> {
+        let mut config = defaults;
+        if let Ok(interval) = env::var(GOSSIP_INTERVAL_ENV) {
+            config.interval = Duration::from_millis(interval.parse()?);
+            if config.interval.is_zero() {
+                anyhow::bail!("{} must be at least 1", GOSSIP_INTERVAL_ENV);
+            }
+        }
+        if let Ok(fanout) = env::var(GOSSIP_FANOUT_ENV) {
+            config.fanout = Some(fanout.parse()?);
+        }
+        Ok(config)
+    }
+}
+
+/// A set of values replicated by gossip that only ever sends each peer the
+/// values it is not known to have.
+///
+/// Every value has an id, which peers acknowledge it by. A peer is known to
+/// have a value once it sent it to us or acknowledged receiving it.
+/// Everything else stays pending for it and goes out again with the next
+/// batch, so values lost to a partition still arrive. Once every peer has
+/// a value only its id is kept, to tell it is not new if it comes again, so
+/// a peer added later only gets the values still held. Ids are kept sorted,
+/// so a batch lists its values in the same order on every run.
+#[derive(Debug, Clone)]
+pub struct DeltaGossip<K, V> {
+    pub config: GossipConfig,
+    known: BTreeSet<K>,
+    /// The values some peer is still owed.
+    items: BTreeMap<K, V>,
+    pending: BTreeMap<String, BTreeSet<K>>,
+}
+
+impl<K: Ord + Clone, V: Clone> DeltaGossip<K, V> {
+    pub fn new(config: GossipConfig, peers: Vec<String>) -> Self {
+        DeltaGossip {
+            config,
+            known: BTreeSet::new(),
+            items: BTreeMap::new(),
+            pending: peers.into_iter().map(|p| (p, BTreeSet::new())).collect(),
+        }
+    }
+
+    /// The values some peer is still owed.
+    pub fn items(&self) -> &BTreeMap<K, V> {
+        &self.items
+    }
+
+    /// Replaces the peers. New peers start out owing every value still held.
+    pub fn set_peers(&mut self, peers: Vec<String>) {
+        let mut pending = BTreeMap::new();
+        for peer in peers {
+            let owed = self
+                .pending
+                .remove(&peer)
+                .unwrap_or_else(|| self.items.keys().cloned().collect());
+            pending.insert(peer, owed);
+        }
+        self.pending = pending;
+        self.collect_garbage();
+    }
+
+    /// Adds `value` as `id`, which every peer in `known_by` already has.
+    /// Returns whether it was new.
+    pub fn insert<'a>(
+        &mut self,
+        id: K,
+        value: V,
+        known_by: impl IntoIterator<Item = &'a str>,
+    ) -> bool {
+        let new = self.known.insert(id.clone());
+        if new {
+            self.items.insert(id.clone(), value);
+            for owed in self.pending.values_mut() {
+                owed.insert(id.clone());
+            }
+        }
+        for peer in known_by {
+            self.ack(peer, [&id]);
+        }
+        new
+    }
+
+    /// Records that `peer` has the values `ids`.
+    pub fn ack<'a>(&mut self, peer: &str, ids: impl IntoIterator<Item = &'a K>)
+    where
+        K: 'a,
+    {
+        if let Some(owed) = self.pending.get_mut(peer) {
+            for id in ids {
+                owed.remove(id);
+            }
+        }
+        self.collect_garbage();
+    }
+
+    /// The peers known to have every one of `ids`.
+    pub fn holders(&self, ids: &[K]) -> Vec<String> {
+        self.pending
+            .iter()
+            .filter(|(_, owed)| ids.iter().all(|id| !owed.contains(id)))
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+
+    /// The values to send this interval, with their ids, for up to `fanout`
+    /// peers that are owed any, picked with `rng`.
+    pub fn batches(&self, rng: &mut impl Rng) -> Vec<(String, Vec<(K, V)>)> {
+        let mut batches: Vec<(String, Vec<(K, V)>)> = self
+            .pending
+            .iter()
+            .filter(|(_, owed)| !owed.is_empty())
+            .map(|(peer, owed)| {
+                let batch = owed
+                    .iter()
+                    .map(|id| (id.clone(), self.items[id].clone()))
+                    .collect();
+                (peer.clone(), batch)
+            })
+            .collect();
+        if let Some(fanout) = self.config.fanout {
+            batches.shuffle(rng);
+            batches.truncate(fanout);
+        }
+        batches
+    }
+
+    /// Drops the values no peer is owed.
+    fn collect_garbage(&mut self) {
+        let pending = &self.pending;
+        self.items
+            .retain(|id, _| pending.values().any(|owed| owed.contains(id)));
+    }
+}
+
+/// Delta-state anti-entropy: replicates a `Crdt` by sending every peer the
+/// join of the deltas it has not acknowledged yet.
+///
+/// Deltas are numbered as they are applied, and a peer acknowledges a batch
+/// by the number it was sent with. Deltas every peer acknowledged are
+/// dropped, a peer that has fallen behind those gets the whole state.
+/// Received deltas that change the state are passed on too, so the state
+/// spreads along any connected graph of peers and stops once every replica
+/// has it.
+#[derive(Debug, Clone)]
+pub struct DeltaSync<C: Crdt> {
+    pub config: GossipConfig,
+    state: C,
+    deltas: BTreeMap<u64, C>,
+    next: u64,
+    /// Every delta numbered below it was dropped.
+    dropped: u64,
+    peers: Vec<String>,
+    /// Every peer has every delta numbered below its entry.
+    acked: HashMap<String, u64>,
+}
+
+impl<C: Crdt> DeltaSync<C> {
+    pub fn new(config: GossipConfig, peers: Vec<String>) -> Self {
+        DeltaSync {
+            config,
+            state: C::default(),
+            deltas: BTreeMap::new(),
+            next: 0,
+            dropped: 0,
+            peers,
+            acked: HashMap::new(),
+        }
+    }
+
+    pub fn state(&self) -> &C {
+        &self.state
+    }
+
+    pub fn set_peers(&mut self, peers: Vec<String>) {
+        self.peers = peers;
+        self.collect_garbage();
+    }
+
+    /// Runs a mutator on the state and queues the delta it returns.
+    pub fn mutate(&mut self, mutator: impl FnOnce(&mut C) -> C) {
+        let delta = mutator(&mut self.state);
+        self.push(delta);
+    }
+
+    /// Merges a delta received from a peer and queues it for the others if
+    /// it was news. Returns whether it was.
+    pub fn receive(&mut self, delta: C) -> bool {
+        let before = self.state.clone();
+        self.state.merge(&delta);
+        let news = self.state != before;
+        if news {
+            self.push(delta);
+        }
+        news
+    }
+
+    /// The newest delta and its number, if it was not dropped yet.
+    pub fn last_delta(&self) -> Option<(u64, &C)> {
+        self.deltas
+            .last_key_value()
+            .filter(|(number, _)| **number + 1 == self.next)
+            .map(|(number, delta)| (*number, delta))
+    }
+
+    /// What to send this interval, for up to `fanout` peers that are missing
+    /// anything, picked with `rng`, with the number to acknowledge it by.
+    pub fn batches(&self, rng: &mut impl Rng) -> Vec<(String, u64, C)> {
+        let mut batches: Vec<(String, u64, C)> = self
+            .peers
+            .iter()
+            .filter_map(|peer| {
+                let from = self.acked.get(peer).copied().unwrap_or(0);
+                let batch = if from < self.dropped {
+                    Some(self.state.clone())
+                } else {
+                    self.deltas.range(from..).fold(None, |batch, (_, delta)| {
+                        let mut batch: C = batch.unwrap_or_default();
+                        batch.merge(delta);
+                        Some(batch)
+                    })
+                };
+                batch.map(|batch| (peer.clone(), self.next, batch))
+            })
+            .collect();
+        if let Some(fanout) = self.config.fanout {
+            batches.shuffle(rng);
+            batches.truncate(fanout);
+        }
+        batches
+    }
+
+    /// Records that `peer` has every delta numbered below `upto`.
+    pub fn ack(&mut self, peer: &str, upto: u64) {
+        let acked = self.acked.entry(peer.to_string()).or_default();
+        *acked = (*acked).max(upto);
+        self.collect_garbage();
+    }
+
+    /// Records that `peer` has the delta numbered `number`, which only tells
+    /// anything if it has every delta before it too.
+    pub fn ack_delta(&mut self, peer: &str, number: u64) {
+        let acked = self.acked.get(peer).copied().unwrap_or(0);
+        if acked == number {
+            self.ack(peer, number + 1);
+        }
+    }
+
+    fn push(&mut self, delta: C) {
+        self.deltas.insert(self.next, delta);
+        self.next += 1;
+    }
+
+    fn collect_garbage(&mut self) {
+        let acked = self
+            .peers
+            .iter()
+            .map(|peer| self.acked.get(peer).copied().unwrap_or(0))
+            .min()
+            .unwrap_or(self.next);
+        if acked > self.dropped {
+            self.deltas = self.deltas.split_off(&acked);
+            self.dropped = acked;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::GSet;
+
+    fn config() -> GossipConfig {
+        GossipConfig {
+            interval: Duration::from_millis(100),
+            fanout: None,
+        }
+    }
+
+    /// Delivers every batch of `from` to `to` and the acknowledgement back.
+    fn sync(from: &mut DeltaSync<GSet<u64>>, to: &mut DeltaSync<GSet<u64>>, to_id: &str) {
+        for (peer, upto, batch) in from.batches(&mut rand::thread_rng()) {
+            if peer == to_id {
+                to.receive(batch);
+                from.ack(&peer, upto);
+            }
+        }
+    }
+
+    #[test]
+    fn delta_sync_converges_and_drops_acked_deltas() {
+        let mut a = DeltaSync::new(config(), vec!["b".to_string()]);
+        let mut b = DeltaSync::new(config(), vec!["a".to_string(), "c".to_string()]);
+        let mut c = DeltaSync::new(config(), vec!["b".to_string()]);
+        a.mutate(|s: &mut GSet<u64>| s.insert(1));
+        c.mutate(|s: &mut GSet<u64>| s.insert(2));
+        for _ in 0..3 {
+            sync(&mut a, &mut b, "b");
+            sync(&mut c, &mut b, "b");
+            sync(&mut b, &mut a, "a");
+            sync(&mut b, &mut c, "c");
+        }
+        for replica in [&a, &b, &c] {
+            assert_eq!(replica.state().iter().copied().collect::<Vec<_>>(), [1, 2]);
+            assert!(replica.deltas.is_empty());
+            assert!(replica.batches(&mut rand::thread_rng()).is_empty());
+        }
+
+        // A peer that joins after deltas were dropped gets the whole state.
+        a.set_peers(vec!["b".to_string(), "d".to_string()]);
+        let batches = a.batches(&mut rand::thread_rng());
+        let (_, _, batch) = batches.iter().find(|(peer, _, _)| peer == "d").unwrap();
+        assert_eq!(batch, a.state());
+    }
+
+    #[test]
+    fn deltas_acked_out_of_order_are_sent_again() {
+        let mut a = DeltaSync::new(config(), vec!["b".to_string()]);
+        let mut b = DeltaSync::new(config(), vec!["a".to_string()]);
+        a.mutate(|s: &mut GSet<u64>| s.insert(1));
+        a.mutate(|s: &mut GSet<u64>| s.insert(2));
+        let (number, delta) = a.last_delta().unwrap();
+        assert_eq!(
+            (number, delta.iter().copied().collect::<Vec<_>>()),
+            (1, vec![2])
+        );
+
+        // The first delta was lost, so the second one's ack is no use.
+        b.receive(delta.clone());
+        a.ack_delta("b", number);
+        sync(&mut a, &mut b, "b");
+        assert_eq!(b.state().iter().copied().collect::<Vec<_>>(), [1, 2]);
+        assert!(a.last_delta().is_none());
+    }
+
+    #[test]
+    fn delta_gossip_drops_values_every_peer_has() {
+        let peers = vec!["b".to_string(), "c".to_string()];
+        let mut gossip = DeltaGossip::new(config(), peers);
+        assert!(gossip.insert(1, "x", ["b"]));
+        assert!(gossip.insert(2, "y", []));
+        let batches = gossip.batches(&mut rand::thread_rng());
+        assert_eq!(
+            batches,
+            [
+                ("b".to_string(), vec![(2, "y")]),
+                ("c".to_string(), vec![(1, "x"), (2, "y")]),
+            ]
+        );
+
+        gossip.ack("c", [&1, &2]);
+        assert_eq!(gossip.items().keys().copied().collect::<Vec<_>>(), [2]);
+        gossip.ack("b", [&2]);
+        assert!(gossip.items().is_empty());
+        assert!(gossip.batches(&mut rand::thread_rng()).is_empty());
+        assert!(!gossip.insert(1, "x", ["c"]));
+        assert!(gossip.items().is_empty());
+    }
+}
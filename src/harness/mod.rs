@@ -0,0 +1,534 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+    fmt::{self, Display},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde_json::{json, Value};
+
+use crate::{
+    check::history::{is_client, outcome, EventKind, History},
+    faults::{Faults, Latency, Timeline},
+    trace, warn, Message,
+};
+
+pub mod services;
+pub mod workload;
+
+pub use services::Services;
+pub use workload::Workload;
+
+/// The id the harness sends `init`, setup and final requests from. It is
+/// not a client, so they stay out of histories read from the journal.
+pub const HARNESS_ID: &str = "harness";
+
+/// How long nodes get to answer `init` and the setup requests.
+const SETUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `lww-kv` replicas exchange their writes.
+const LWW_SYNC_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The node binary, started once per node.
+    pub binary: PathBuf,
+    pub nodes: usize,
+    /// How many clients send requests at the same time.
+    pub clients: usize,
+    /// How long the clients send requests for.
+    pub time_limit: Duration,
+    /// Requests per second, over all clients.
+    pub rate: f64,
+    /// How long a client waits for a reply before it gives up on a request.
+    pub timeout: Duration,
+    /// How long the cluster gets to settle before the final requests.
+    pub settle: Duration,
+    pub latency: Latency,
+    /// Faults between nodes, with times counted from the start of the run.
+    pub faults: Timeline,
+    /// Seeds the requests and the network.
+    pub seed: u64,
+    /// Where to write every message delivered, one per line, for `check`.
+    pub journal: Option<PathBuf>,
+    /// Where to write the stderr of every node, as `<node>.log`, instead of
+    /// the harness's own.
+    pub log_dir: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            binary: PathBuf::new(),
+            nodes: 3,
+            clients: 3,
+            time_limit: Duration::from_secs(5),
+            rate: 10.0,
+            timeout: Duration::from_secs(1),
+            settle: Duration::from_secs(1),
+            latency: Latency::default(),
+            faults: Timeline::default(),
+            seed: 0,
+            journal: None,
+            log_dir: None,
+        }
+    }
+}
+
+/// How a run went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub ok: usize,
+    pub failed: usize,
+    pub indeterminate: usize,
+    /// What is wrong with the run, if anything.
+    pub problem: Option<String>,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ok, {} failed, {} indeterminate",
+            self.ok, self.failed, self.indeterminate
+        )?;
+        match &self.problem {
+            None => writeln!(f, "valid"),
+            Some(problem) => writeln!(f, "invalid: {}", problem.trim_end()),
+        }
+    }
+}
+
+/// A line from a node, or the end of its output.
+enum Output {
+    Line(String),
+    Closed(String),
+}
+
+/// A message on its way. `seq` keeps messages due at the same time in the
+/// order they were sent.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct InFlight {
+    at: Instant,
+    seq: u64,
+    dest: String,
+    line: String,
+}
+
+/// A client with at most one request outstanding.
+struct Client {
+    id: String,
+    /// The node it sends its requests to.
+    node: String,
+    /// The `msg_id` of the outstanding request, and when to give up on it.
+    pending: Option<(usize, Instant)>,
+    next_at: Instant,
+}
+
+/// Runs `workload` against a cluster of `config.nodes` processes of
+/// `config.binary`, named `n0`, `n1`, ...
+///
+/// The harness stands in for maelstrom. It sends every node `init`, routes
+/// what the nodes write by `dest`, with a latency and whatever the fault
+/// timeline does to messages between nodes, and plays the clients: every
+/// client sticks to one node and sends the next request some time after
+/// the last one completed. A client that gives up on a request carries on
+/// under a new id, as the request may still take effect. Requests to
+/// `seq-kv`, `lin-kv`, `lww-kv` and `lin-tso` are answered by `Services`,
+/// whose `lww-kv` replicas sync unless the faults cut their nodes off from
+/// each other. Once the time limit is up, the cluster settles and every node
+/// gets the final request, and the workload checks what happened.
+pub fn run(config: &Config, workload: &mut dyn Workload) -> anyhow::Result<Report> {
+    let mut harness = Harness::start(config)?;
+    let result = harness.drive(workload);
+    harness.stop();
+    result
+}
+
+struct Harness<'a> {
+    config: &'a Config,
+    node_ids: Vec<String>,
+    processes: BTreeMap<String, (Child, ChildStdin)>,
+    output: Receiver<Output>,
+    faults: Faults,
+    rng: StdRng,
+    start: Instant,
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    sent: u64,
+    msg_id: usize,
+    journal: Option<BufWriter<File>>,
+    clients: Vec<Client>,
+    next_client: usize,
+    history: History,
+    /// Replies to the requests of the harness itself, by `msg_id`.
+    replies: HashMap<usize, Value>,
+    services: Services,
+    next_sync: Instant,
+}
+
+impl<'a> Harness<'a> {
+    fn start(config: &'a Config) -> anyhow::Result<Self> {
+        let node_ids: Vec<String> = (0..config.nodes).map(|i| format!("n{}", i)).collect();
+        let (tx, output) = channel();
+        let mut processes = BTreeMap::new();
+        for node_id in &node_ids {
+            let stderr = match &config.log_dir {
+                Some(dir) => Stdio::from(File::create(dir.join(format!("{}.log", node_id)))?),
+                None => Stdio::inherit(),
+            };
+            let mut child = Command::new(&config.binary)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(stderr)
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("cannot start {:?}: {}", config.binary, e))?;
+            let stdin = child.stdin.take().unwrap();
+            let stdout = child.stdout.take().unwrap();
+            let tx = tx.clone();
+            let node = node_id.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    if tx.send(Output::Line(line)).is_err() {
+                        return;
+                    }
+                }
+                let _ = tx.send(Output::Closed(node));
+            });
+            processes.insert(node_id.clone(), (child, stdin));
+        }
+        let journal = match &config.journal {
+            Some(path) => Some(BufWriter::new(File::create(path)?)),
+            None => None,
+        };
+        Ok(Harness {
+            config,
+            faults: Faults::new(config.faults.clone(), node_ids.clone()),
+            node_ids,
+            processes,
+            output,
+            rng: StdRng::seed_from_u64(config.seed),
+            start: Instant::now(),
+            in_flight: BinaryHeap::new(),
+            sent: 0,
+            msg_id: 0,
+            journal,
+            clients: Vec::new(),
+            next_client: 1,
+            history: History::default(),
+            replies: HashMap::new(),
+            services: Services::default(),
+            next_sync: Instant::now(),
+        })
+    }
+
+    fn drive(&mut self, workload: &mut dyn Workload) -> anyhow::Result<Report> {
+        let inits = self
+            .node_ids
+            .iter()
+            .map(|id| {
+                let init = json!({"type": "init", "node_id": id, "node_ids": self.node_ids});
+                (id.clone(), init)
+            })
+            .collect();
+        for (node, reply) in self.call_all(inits, SETUP_TIMEOUT)? {
+            if reply.is_none() {
+                return Err(anyhow::anyhow!("{} did not answer init", node));
+            }
+        }
+        let setup = workload.setup(&self.node_ids);
+        for (node, reply) in self.call_all(setup, SETUP_TIMEOUT)? {
+            if reply.is_none_or(|reply| outcome(&reply) != EventKind::Ok) {
+                return Err(anyhow::anyhow!("{} failed the setup", node));
+            }
+        }
+
+        let now = Instant::now();
+        for i in 0..self.config.clients {
+            let id = self.client_id();
+            let node = self.node_ids[i % self.node_ids.len()].clone();
+            self.clients.push(Client {
+                id,
+                node,
+                pending: None,
+                next_at: now,
+            });
+        }
+        let end = now + self.config.time_limit;
+        while Instant::now() < end {
+            self.send_requests(workload)?;
+            self.step(end)?;
+        }
+        // Waits for the outstanding requests, then lets the cluster settle.
+        let drained = Instant::now() + self.config.timeout;
+        loop {
+            self.expire();
+            self.clients.retain(|c| c.pending.is_some());
+            if self.clients.is_empty() || Instant::now() >= drained {
+                break;
+            }
+            self.step(drained)?;
+        }
+        self.clients.clear();
+        let settled = Instant::now() + self.config.settle;
+        while Instant::now() < settled {
+            self.step(settled)?;
+        }
+
+        let finals = match workload.final_request() {
+            Some(request) => {
+                let requests = self
+                    .node_ids
+                    .iter()
+                    .map(|id| (id.clone(), request.clone()))
+                    .collect();
+                self.call_all(requests, self.config.timeout)?
+            }
+            None => Vec::new(),
+        };
+        if let Some(journal) = &mut self.journal {
+            journal.flush()?;
+        }
+
+        let operations = self.history.operations();
+        let count = |kind| operations.iter().filter(|op| op.kind == kind).count();
+        let (ok, failed, indeterminate) = (
+            count(EventKind::Ok),
+            count(EventKind::Fail),
+            count(EventKind::Info),
+        );
+        let problem = if ok == 0 {
+            Some("no operation succeeded".to_string())
+        } else {
+            workload.check(&self.history, &finals)?
+        };
+        Ok(Report {
+            ok,
+            failed,
+            indeterminate,
+            problem,
+        })
+    }
+
+    fn client_id(&mut self) -> String {
+        self.next_client += 1;
+        format!("c{}", self.next_client - 1)
+    }
+
+    /// Has every idle client whose time has come send its next request.
+    fn send_requests(&mut self, workload: &mut dyn Workload) -> anyhow::Result<()> {
+        let now = Instant::now();
+        self.expire();
+        for i in 0..self.clients.len() {
+            let client = &self.clients[i];
+            if client.pending.is_some() || client.next_at > now {
+                continue;
+            }
+            let (id, node) = (client.id.clone(), client.node.clone());
+            let request = workload.request(&mut self.rng);
+            let msg_id = self.send_request(&id, &node, request.clone())?;
+            self.history.push(EventKind::Invoke, id, msg_id, request);
+            self.clients[i].pending = Some((msg_id, now + self.config.timeout));
+        }
+        Ok(())
+    }
+
+    /// Gives up on the requests that timed out. Their clients carry on
+    /// under a new id.
+    fn expire(&mut self) {
+        let now = Instant::now();
+        for i in 0..self.clients.len() {
+            if self.clients[i]
+                .pending
+                .is_some_and(|(_, deadline)| deadline <= now)
+            {
+                let id = self.client_id();
+                let client = &mut self.clients[i];
+                client.id = id;
+                client.pending = None;
+                client.next_at = now;
+            }
+        }
+    }
+
+    /// Sends every request from `HARNESS_ID` and waits for the replies,
+    /// for at most `timeout`.
+    fn call_all(
+        &mut self,
+        requests: Vec<(String, Value)>,
+        timeout: Duration,
+    ) -> anyhow::Result<Vec<(String, Option<Value>)>> {
+        let mut calls = Vec::new();
+        for (node, request) in requests {
+            let msg_id = self.send_request(HARNESS_ID, &node, request)?;
+            calls.push((node, msg_id));
+        }
+        let end = Instant::now() + timeout;
+        while Instant::now() < end && calls.iter().any(|(_, id)| !self.replies.contains_key(id)) {
+            self.step(end)?;
+        }
+        Ok(calls
+            .into_iter()
+            .map(|(node, msg_id)| (node, self.replies.remove(&msg_id)))
+            .collect())
+    }
+
+    fn send_request(&mut self, src: &str, dest: &str, payload: Value) -> anyhow::Result<usize> {
+        let message = Message::new(src.to_string(), dest.to_string(), payload, &mut self.msg_id);
+        self.send(serde_json::to_string(&message)?);
+        Ok(self.msg_id)
+    }
+
+    /// Puts a line on the network.
+    fn send(&mut self, line: String) {
+        let message = match serde_json::from_str::<Message<Value>>(&line) {
+            Ok(message) => message,
+            Err(error) => {
+                warn!("dropping unroutable line {:?}: {}", line, error);
+                return;
+            }
+        };
+        let now = Instant::now();
+        let fate = self.faults.fate(
+            now - self.start,
+            &message.src,
+            &message.dest,
+            &self.config.latency,
+            &mut self.rng,
+        );
+        trace!("{} -> {}: {}", message.src, message.dest, fate);
+        for delay in fate.delays {
+            self.sent += 1;
+            self.in_flight.push(Reverse(InFlight {
+                at: now + delay,
+                seq: self.sent,
+                dest: message.dest.clone(),
+                line: line.clone(),
+            }));
+        }
+    }
+
+    /// Delivers the messages that are due, then waits for output from the
+    /// nodes until the next message or client is due, or `until`.
+    fn step(&mut self, until: Instant) -> anyhow::Result<()> {
+        while let Some(Reverse(message)) = self.in_flight.peek() {
+            if message.at > Instant::now() {
+                break;
+            }
+            let Reverse(message) = self.in_flight.pop().unwrap();
+            self.deliver(message)?;
+        }
+        if self.next_sync <= Instant::now() {
+            self.sync_lww();
+        }
+
+        let next_client = self.clients.iter().map(|c| match c.pending {
+            Some((_, deadline)) => deadline,
+            None => c.next_at,
+        });
+        let next_message = self.in_flight.peek().map(|Reverse(m)| m.at);
+        let wake = next_client
+            .chain(next_message)
+            .chain([self.next_sync, until])
+            .min()
+            .unwrap();
+        let mut timeout = wake.saturating_duration_since(Instant::now());
+        loop {
+            match self.output.recv_timeout(timeout) {
+                Ok(Output::Line(line)) => self.send(line),
+                Ok(Output::Closed(node)) => {
+                    return Err(anyhow::anyhow!("{} exited", node));
+                }
+                Err(RecvTimeoutError::Timeout) => return Ok(()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow::anyhow!("every node exited"));
+                }
+            }
+            // Takes whatever else is there without waiting.
+            timeout = Duration::ZERO;
+        }
+    }
+
+    fn deliver(&mut self, message: InFlight) -> anyhow::Result<()> {
+        if let Some(journal) = &mut self.journal {
+            writeln!(journal, "{}", message.line)?;
+        }
+        if let Some((_, stdin)) = self.processes.get_mut(&message.dest) {
+            writeln!(stdin, "{}", message.line)
+                .map_err(|e| anyhow::anyhow!("cannot write to {}: {}", message.dest, e))?;
+            return Ok(());
+        }
+        let reply: Message<Value> = serde_json::from_str(&message.line)?;
+        if let Some(answer) = self.services.handle(&reply, &mut self.rng)? {
+            self.send(serde_json::to_string(&answer)?);
+            return Ok(());
+        }
+        let Some(in_reply_to) = reply.body.in_reply_to else {
+            warn!("dropping a message to {}, which is no node", message.dest);
+            return Ok(());
+        };
+        if message.dest == HARNESS_ID {
+            self.replies.insert(in_reply_to, reply.body.payload);
+        } else if is_client(&message.dest) {
+            let now = Instant::now();
+            let client = self.clients.iter_mut().find(|c| {
+                c.id == message.dest && c.pending.is_some_and(|(id, _)| id == in_reply_to)
+            });
+            // A late reply to a request the client gave up on is ignored.
+            if let Some(client) = client {
+                client.pending = None;
+                let mean = self.config.clients as f64 / self.config.rate;
+                client.next_at =
+                    now + Latency::Exponential(Duration::from_secs_f64(mean)).sample(&mut self.rng);
+                let kind = outcome(&reply.body.payload);
+                self.history
+                    .push(kind, message.dest, in_reply_to, reply.body.payload);
+            }
+        } else {
+            warn!("dropping a message to {}, which is no node", message.dest);
+        }
+        Ok(())
+    }
+
+    /// Syncs every pair of `lww-kv` replicas whose nodes can reach each
+    /// other right now.
+    fn sync_lww(&mut self) {
+        let now = Instant::now();
+        let replicas = self.services.lww_replicas();
+        for from in &replicas {
+            for to in replicas.iter().filter(|to| *to != from) {
+                let fate = self.faults.fate(
+                    now - self.start,
+                    from,
+                    to,
+                    &self.config.latency,
+                    &mut self.rng,
+                );
+                if !fate.delays.is_empty() {
+                    self.services.sync_lww(from, to);
+                }
+            }
+        }
+        self.next_sync = now + LWW_SYNC_INTERVAL;
+    }
+
+    fn stop(&mut self) {
+        for (_, (mut child, stdin)) in std::mem::take(&mut self.processes) {
+            drop(stdin);
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rand::Rng;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    error::{Error, ErrorCode},
+    kv::{KvPayload, Service},
+    tso::{TsoPayload, TSO_SERVICE},
+    Message,
+};
+
+/// Registers by the JSON text of their key.
+type Registers = HashMap<String, Value>;
+
+/// Runs a request against `registers`, returning the reply and the key it
+/// wrote, if any.
+fn apply(
+    registers: &mut Registers,
+    request: KvPayload,
+) -> Result<(KvPayload, Option<String>), Error> {
+    match request {
+        KvPayload::Read { key } => match registers.get(&key.to_string()) {
+            Some(value) => Ok((
+                KvPayload::ReadOk {
+                    value: value.clone(),
+                },
+                None,
+            )),
+            None => Err(missing(&key)),
+        },
+        KvPayload::Write { key, value } => {
+            registers.insert(key.to_string(), value);
+            Ok((KvPayload::WriteOk, Some(key.to_string())))
+        }
+        KvPayload::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        } => match registers.get(&key.to_string()) {
+            Some(value) if *value != from => Err(Error::new(
+                ErrorCode::PreconditionFailed,
+                format!("expected {}, but had {}", from, value),
+            )),
+            None if !create_if_not_exists => Err(missing(&key)),
+            _ => {
+                registers.insert(key.to_string(), to);
+                Ok((KvPayload::CasOk, Some(key.to_string())))
+            }
+        },
+        reply => Err(Error::new(
+            ErrorCode::NotSupported,
+            format!("{:?} is no request", reply),
+        )),
+    }
+}
+
+fn to_value(result: Result<impl Serialize, Error>) -> serde_json::Result<Value> {
+    match result {
+        Ok(reply) => serde_json::to_value(reply),
+        Err(error) => serde_json::to_value(error),
+    }
+}
+
+fn missing(key: &Value) -> Error {
+    Error::new(
+        ErrorCode::KeyDoesNotExist,
+        format!("key {} does not exist", key),
+    )
+}
+
+/// Sequentially consistent registers. Writes and compare-and-sets all apply
+/// to the latest state, one after the other, but a read may be served from
+/// any state between the last one its client saw and the latest, so clients
+/// can lag behind.
+#[derive(Debug, Default)]
+struct SeqKv {
+    latest: Registers,
+    /// Every value a key had, with the number of the write that set it.
+    versions: HashMap<String, Vec<(usize, Value)>>,
+    writes: usize,
+    /// The last state every client saw, as a number of writes.
+    seen: HashMap<String, usize>,
+}
+
+impl SeqKv {
+    fn handle(
+        &mut self,
+        client: &str,
+        request: KvPayload,
+        rng: &mut impl Rng,
+    ) -> Result<KvPayload, Error> {
+        if let KvPayload::Read { key } = &request {
+            let seen = self.seen.get(client).copied().unwrap_or(0);
+            let at = rng.gen_range(seen..=self.writes);
+            self.seen.insert(client.to_string(), at);
+            let value = self
+                .versions
+                .get(&key.to_string())
+                .and_then(|versions| versions.iter().rev().find(|(write, _)| *write <= at));
+            return match value {
+                Some((_, value)) => Ok(KvPayload::ReadOk {
+                    value: value.clone(),
+                }),
+                None => Err(missing(key)),
+            };
+        }
+        let result = apply(&mut self.latest, request);
+        if let Ok((_, Some(key))) = &result {
+            self.writes += 1;
+            let value = self.latest[key].clone();
+            self.versions
+                .entry(key.clone())
+                .or_default()
+                .push((self.writes, value));
+        }
+        self.seen.insert(client.to_string(), self.writes);
+        result.map(|(reply, _)| reply)
+    }
+}
+
+/// Last-writer-wins registers with a replica per client. Requests only go
+/// to the client's own replica, where writes are stamped with a clock
+/// shared by all of them. Replicas learn of each other's writes when they
+/// are synced and keep the one with the highest stamp, so until then they
+/// diverge and a compare-and-set only sees its own replica.
+#[derive(Debug, Default)]
+struct LwwKv {
+    replicas: BTreeMap<String, Replica>,
+    clock: u64,
+}
+
+#[derive(Debug, Default)]
+struct Replica {
+    registers: Registers,
+    /// When every register was written, and by whom, to break ties.
+    stamps: HashMap<String, (u64, String)>,
+}
+
+impl LwwKv {
+    fn handle(&mut self, client: &str, request: KvPayload) -> Result<KvPayload, Error> {
+        let replica = self.replicas.entry(client.to_string()).or_default();
+        let (reply, written) = apply(&mut replica.registers, request)?;
+        if let Some(key) = written {
+            self.clock += 1;
+            replica.stamps.insert(key, (self.clock, client.to_string()));
+        }
+        Ok(reply)
+    }
+
+    fn sync(&mut self, from: &str, to: &str) {
+        let Some(source) = self.replicas.get(from) else {
+            return;
+        };
+        let newer: Vec<(String, Value, (u64, String))> = source
+            .stamps
+            .iter()
+            .map(|(key, stamp)| (key.clone(), source.registers[key].clone(), stamp.clone()))
+            .collect();
+        let replica = self.replicas.entry(to.to_string()).or_default();
+        for (key, value, stamp) in newer {
+            if replica
+                .stamps
+                .get(&key)
+                .is_none_or(|current| *current < stamp)
+            {
+                replica.registers.insert(key.clone(), value);
+                replica.stamps.insert(key, stamp);
+            }
+        }
+    }
+}
+
+/// The services maelstrom runs next to the nodes, for the harness:
+/// `lin-kv` is a single map every request runs against in the order it
+/// arrives, `seq-kv` is a `SeqKv`, `lww-kv` a `LwwKv` and `lin-tso` a
+/// counter, so its timestamps follow the order requests arrive in as well.
+#[derive(Debug, Default)]
+pub struct Services {
+    lin_kv: Registers,
+    seq_kv: SeqKv,
+    lww_kv: LwwKv,
+    /// The last timestamp `lin-tso` handed out.
+    tso: u64,
+    msg_id: usize,
+}
+
+impl Services {
+    /// Answers a request sent to one of the services, or returns `None` if
+    /// its `dest` is none of them.
+    pub fn handle(
+        &mut self,
+        message: &Message<Value>,
+        rng: &mut impl Rng,
+    ) -> anyhow::Result<Option<Message<Value>>> {
+        let payload = if let Some(service) = Service::from_name(&message.dest) {
+            to_value(self.kv(service, message, rng))?
+        } else if message.dest == TSO_SERVICE {
+            to_value(self.tso(message))?
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(message.reply(payload, &mut self.msg_id)))
+    }
+
+    fn kv(
+        &mut self,
+        service: Service,
+        message: &Message<Value>,
+        rng: &mut impl Rng,
+    ) -> Result<KvPayload, Error> {
+        let client = message.src.as_str();
+        let request = serde_json::from_value(message.body.payload.clone())
+            .map_err(|error| Error::new(ErrorCode::MalformedRequest, error.to_string()))?;
+        match service {
+            Service::LinKv => apply(&mut self.lin_kv, request).map(|(reply, _)| reply),
+            Service::SeqKv => self.seq_kv.handle(client, request, rng),
+            Service::LwwKv => self.lww_kv.handle(client, request),
+        }
+    }
+
+    fn tso(&mut self, message: &Message<Value>) -> Result<TsoPayload, Error> {
+        let request = serde_json::from_value(message.body.payload.clone())
+            .map_err(|error| Error::new(ErrorCode::MalformedRequest, error.to_string()))?;
+        match request {
+            TsoPayload::Ts => {
+                self.tso += 1;
+                Ok(TsoPayload::TsOk { ts: self.tso })
+            }
+            reply => Err(Error::new(
+                ErrorCode::NotSupported,
+                format!("{:?} is no request", reply),
+            )),
+        }
+    }
+
+    /// The clients with an `lww-kv` replica.
+    pub fn lww_replicas(&self) -> Vec<String> {
+        self.lww_kv.replicas.keys().cloned().collect()
+    }
+
+    /// Has the `lww-kv` replica of `to` learn the writes of the one of
+    /// `from`.
+    pub fn sync_lww(&mut self, from: &str, to: &str) {
+        self.lww_kv.sync(from, to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    use super::*;
+
+    fn call(services: &mut Services, service: Service, src: &str, payload: Value) -> Value {
+        call_dest(services, service.name(), src, payload)
+    }
+
+    fn call_dest(services: &mut Services, dest: &str, src: &str, payload: Value) -> Value {
+        let mut rng = StdRng::seed_from_u64(0);
+        let request = Message::new(src.to_string(), dest.to_string(), payload, &mut 0);
+        let reply = services.handle(&request, &mut rng).unwrap().unwrap();
+        assert_eq!(reply.body.in_reply_to, Some(1));
+        reply.body.payload
+    }
+
+    #[test]
+    fn seq_kv_reads_never_go_back_in_time() {
+        let mut services = Services::default();
+        let mut rng = StdRng::seed_from_u64(1);
+        for value in 0..20 {
+            let write = json!({"type": "write", "key": "k", "value": value});
+            services
+                .seq_kv
+                .handle("n0", serde_json::from_value(write).unwrap(), &mut rng)
+                .unwrap();
+        }
+        let mut read = |client: &str| {
+            let read = serde_json::from_value(json!({"type": "read", "key": "k"})).unwrap();
+            match services.seq_kv.handle(client, read, &mut rng) {
+                Ok(KvPayload::ReadOk { value }) => value.as_i64().unwrap(),
+                _ => -1,
+            }
+        };
+        let mut last = -1;
+        for _ in 0..20 {
+            let value = read("n1");
+            assert!(value >= last);
+            last = value;
+        }
+        assert!((2..20).any(|n| read(&format!("n{}", n)) < 19));
+
+        // Whoever wrote last has seen it.
+        let read = json!({"type": "read", "key": "k"});
+        assert_eq!(call(&mut services, Service::SeqKv, "n0", read)["value"], 19);
+    }
+
+    #[test]
+    fn lww_kv_replicas_diverge_until_synced() {
+        let mut services = Services::default();
+        let write = |value| json!({"type": "write", "key": 1, "value": value});
+        call(&mut services, Service::LwwKv, "n0", write(1));
+        call(&mut services, Service::LwwKv, "n1", write(2));
+        let cas = json!({"type": "cas", "key": 1, "from": 1, "to": 3});
+        assert_eq!(
+            call(&mut services, Service::LwwKv, "n0", cas)["type"],
+            "cas_ok"
+        );
+
+        services.sync_lww("n1", "n0");
+        let read = json!({"type": "read", "key": 1});
+        assert_eq!(
+            call(&mut services, Service::LwwKv, "n0", read.clone())["value"],
+            3
+        );
+        services.sync_lww("n0", "n1");
+        assert_eq!(call(&mut services, Service::LwwKv, "n1", read)["value"], 3);
+    }
+
+    #[test]
+    fn lin_kv_answers_like_maelstrom() {
+        let mut services = Services::default();
+        let read = json!({"type": "read", "key": "k"});
+        assert_eq!(
+            call(&mut services, Service::LinKv, "n0", read.clone())["code"],
+            20
+        );
+        let cas =
+            json!({"type": "cas", "key": "k", "from": 1, "to": 2, "create_if_not_exists": true});
+        assert_eq!(
+            call(&mut services, Service::LinKv, "n0", cas.clone())["type"],
+            "cas_ok"
+        );
+        assert_eq!(call(&mut services, Service::LinKv, "n1", cas)["code"], 22);
+        assert_eq!(call(&mut services, Service::LinKv, "n1", read)["value"], 2);
+    }
+
+    #[test]
+    fn lin_tso_timestamps_increase() {
+        let mut services = Services::default();
+        let ts = json!({"type": "ts"});
+        let mut last = 0;
+        for client in ["n0", "n1", "n0", "n2"] {
+            let reply = call_dest(&mut services, TSO_SERVICE, client, ts.clone());
+            assert_eq!(reply["type"], "ts_ok");
+            let ts = reply["ts"].as_u64().unwrap();
+            assert!(ts > last);
+            last = ts;
+        }
+        let ts_ok = json!({"type": "ts_ok", "ts": 1});
+        assert_eq!(
+            call_dest(&mut services, TSO_SERVICE, "n0", ts_ok)["code"],
+            10
+        );
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let request = Message::new("n0".to_string(), "n1".to_string(), json!({}), &mut 0);
+        assert!(services.handle(&request, &mut rng).unwrap().is_none());
+    }
+}
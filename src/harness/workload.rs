@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, HashSet};
+
+use rand::{rngs::StdRng, Rng};
+use serde_json::{json, Value};
+
+use crate::{
+    check::{
+        history::{EventKind, History},
+        linearizable,
+        txn::{self, Level},
+    },
+    topology::Overlay,
+};
+
+/// What the clients of a run do, and how to tell whether the cluster got
+/// it right.
+pub trait Workload {
+    /// Requests every node gets before the clients start, such as
+    /// `topology`.
+    fn setup(&mut self, _node_ids: &[String]) -> Vec<(String, Value)> {
+        Vec::new()
+    }
+
+    /// The next request of a client.
+    fn request(&mut self, rng: &mut StdRng) -> Value;
+
+    /// The request every node gets once the clients are done and the
+    /// cluster has settled, such as a last `read`.
+    fn final_request(&self) -> Option<Value> {
+        None
+    }
+
+    /// Checks the client operations and the replies to the final requests,
+    /// `None` where a node did not answer. Returns what is wrong, if
+    /// anything.
+    fn check(
+        &self,
+        history: &History,
+        finals: &[(String, Option<Value>)],
+    ) -> anyhow::Result<Option<String>>;
+}
+
+/// The workload called `name`: `echo`, `unique-ids`, `broadcast`,
+/// `g-counter`, `pn-counter`, `lin-kv` or `txn-rw-register`.
+pub fn by_name(name: &str) -> anyhow::Result<Box<dyn Workload>> {
+    Ok(match name {
+        "echo" => Box::new(Echo),
+        "unique-ids" => Box::new(UniqueIds),
+        "broadcast" => Box::new(Broadcast::default()),
+        "g-counter" => Box::new(Counter { negative: false }),
+        "pn-counter" => Box::new(Counter { negative: true }),
+        "lin-kv" => Box::new(LinKv { keys: 5 }),
+        "txn-rw-register" => Box::new(TxnRwRegister::default()),
+        _ => return Err(anyhow::anyhow!("unknown workload {:?}", name)),
+    })
+}
+
+/// The replies of the operations that succeeded, with their requests.
+fn completed(history: &History) -> impl Iterator<Item = (Value, Value)> {
+    history
+        .operations()
+        .into_iter()
+        .filter(|op| op.kind == EventKind::Ok)
+        .filter_map(|op| Some((op.request, op.reply?)))
+}
+
+/// Whether every node answered its final request.
+fn all_answered(finals: &[(String, Option<Value>)]) -> Option<String> {
+    let missing: Vec<&str> = finals
+        .iter()
+        .filter(|(_, reply)| reply.is_none())
+        .map(|(node, _)| node.as_str())
+        .collect();
+    (!missing.is_empty()).then(|| format!("no final read from {}", missing.join(", ")))
+}
+
+pub struct Echo;
+
+impl Workload for Echo {
+    fn request(&mut self, rng: &mut StdRng) -> Value {
+        json!({"type": "echo", "echo": format!("please echo {}", rng.gen_range(0..128))})
+    }
+
+    fn check(
+        &self,
+        history: &History,
+        _: &[(String, Option<Value>)],
+    ) -> anyhow::Result<Option<String>> {
+        for (request, reply) in completed(history) {
+            if reply.get("echo") != request.get("echo") {
+                return Ok(Some(format!("{} was echoed as {}", request, reply)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub struct UniqueIds;
+
+impl Workload for UniqueIds {
+    fn request(&mut self, _: &mut StdRng) -> Value {
+        json!({"type": "generate"})
+    }
+
+    fn check(
+        &self,
+        history: &History,
+        _: &[(String, Option<Value>)],
+    ) -> anyhow::Result<Option<String>> {
+        let mut ids = HashSet::new();
+        for (_, reply) in completed(history) {
+            let id = reply.get("id").cloned().unwrap_or(Value::Null);
+            if !ids.insert(id.to_string()) {
+                return Ok(Some(format!("id {} was generated twice", id)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Broadcasts distinct numbers and reads them back. Every acknowledged
+/// broadcast has to reach every node by the final reads, and nobody may
+/// read a number that was never broadcast.
+pub struct Broadcast {
+    /// The topology every node is sent.
+    pub overlay: Overlay,
+    next: usize,
+}
+
+impl Default for Broadcast {
+    fn default() -> Self {
+        Broadcast {
+            overlay: Overlay::Tree { branching: 4 },
+            next: 0,
+        }
+    }
+}
+
+impl Workload for Broadcast {
+    fn setup(&mut self, node_ids: &[String]) -> Vec<(String, Value)> {
+        let topology: BTreeMap<&String, Vec<String>> = node_ids
+            .iter()
+            .map(|id| {
+                let neighbours = self.overlay.neighbours(id, node_ids);
+                (id, neighbours.unwrap_or_default())
+            })
+            .collect();
+        node_ids
+            .iter()
+            .map(|id| {
+                (
+                    id.clone(),
+                    json!({"type": "topology", "topology": topology}),
+                )
+            })
+            .collect()
+    }
+
+    fn request(&mut self, rng: &mut StdRng) -> Value {
+        if rng.gen_bool(0.5) {
+            return json!({"type": "read"});
+        }
+        self.next += 1;
+        json!({"type": "broadcast", "message": self.next})
+    }
+
+    fn final_request(&self) -> Option<Value> {
+        Some(json!({"type": "read"}))
+    }
+
+    fn check(
+        &self,
+        history: &History,
+        finals: &[(String, Option<Value>)],
+    ) -> anyhow::Result<Option<String>> {
+        if let Some(problem) = all_answered(finals) {
+            return Ok(Some(problem));
+        }
+        let sent: HashSet<String> = history
+            .operations()
+            .into_iter()
+            .filter_map(|op| op.request.get("message").map(Value::to_string))
+            .collect();
+        let acknowledged: Vec<String> = completed(history)
+            .filter_map(|(request, _)| request.get("message").map(Value::to_string))
+            .collect();
+        let mut reads: Vec<(String, Value)> = completed(history)
+            .filter(|(request, _)| request.get("type") == Some(&json!("read")))
+            .map(|(_, reply)| (String::from("a client"), reply))
+            .collect();
+        reads.extend(
+            finals
+                .iter()
+                .filter_map(|(node, reply)| Some((node.clone(), reply.clone()?))),
+        );
+        for (reader, reply) in &reads {
+            let messages = reply.get("messages").and_then(Value::as_array);
+            for message in messages.into_iter().flatten() {
+                if !sent.contains(&message.to_string()) {
+                    return Ok(Some(format!(
+                        "{} read {}, which nobody sent",
+                        reader, message
+                    )));
+                }
+            }
+        }
+        for (node, reply) in finals {
+            let read: HashSet<String> = reply
+                .iter()
+                .filter_map(|reply| reply.get("messages").and_then(Value::as_array))
+                .flatten()
+                .map(Value::to_string)
+                .collect();
+            let lost: Vec<&str> = acknowledged
+                .iter()
+                .filter(|message| !read.contains(*message))
+                .map(String::as_str)
+                .collect();
+            if !lost.is_empty() {
+                return Ok(Some(format!("{} never got {}", node, lost.join(", "))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Adds to a counter. Once the cluster has settled every node has to read
+/// the sum of the acknowledged deltas, give or take the ones without a
+/// definite outcome. With `negative` deltas can be below zero.
+pub struct Counter {
+    pub negative: bool,
+}
+
+impl Workload for Counter {
+    fn request(&mut self, rng: &mut StdRng) -> Value {
+        if rng.gen_bool(0.25) {
+            return json!({"type": "read"});
+        }
+        let delta: i64 = if self.negative {
+            rng.gen_range(-5..=5)
+        } else {
+            rng.gen_range(0..=5)
+        };
+        json!({"type": "add", "delta": delta})
+    }
+
+    fn final_request(&self) -> Option<Value> {
+        Some(json!({"type": "read"}))
+    }
+
+    fn check(
+        &self,
+        history: &History,
+        finals: &[(String, Option<Value>)],
+    ) -> anyhow::Result<Option<String>> {
+        if let Some(problem) = all_answered(finals) {
+            return Ok(Some(problem));
+        }
+        let (mut lowest, mut highest) = (0, 0);
+        for op in history.operations() {
+            let Some(delta) = op.request.get("delta").and_then(Value::as_i64) else {
+                continue;
+            };
+            match op.kind {
+                EventKind::Ok => {
+                    lowest += delta;
+                    highest += delta;
+                }
+                EventKind::Info => {
+                    lowest += delta.min(0);
+                    highest += delta.max(0);
+                }
+                EventKind::Fail | EventKind::Invoke => {}
+            }
+        }
+        for (node, reply) in finals {
+            let value = reply.as_ref().and_then(|r| r.get("value"));
+            match value.and_then(Value::as_i64) {
+                Some(value) if (lowest..=highest).contains(&value) => {}
+                _ => {
+                    return Ok(Some(format!(
+                        "{} read {}, expected between {} and {}",
+                        node,
+                        value.unwrap_or(&Value::Null),
+                        lowest,
+                        highest
+                    )))
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Reads, writes and compare-and-sets on a few keys, checked for
+/// linearizability.
+pub struct LinKv {
+    pub keys: u64,
+}
+
+impl Workload for LinKv {
+    fn request(&mut self, rng: &mut StdRng) -> Value {
+        let key = rng.gen_range(0..self.keys);
+        match rng.gen_range(0..3) {
+            0 => json!({"type": "read", "key": key}),
+            1 => json!({"type": "write", "key": key, "value": rng.gen_range(0..5)}),
+            _ => json!({
+                "type": "cas",
+                "key": key,
+                "from": rng.gen_range(0..5),
+                "to": rng.gen_range(0..5),
+            }),
+        }
+    }
+
+    fn check(
+        &self,
+        history: &History,
+        _: &[(String, Option<Value>)],
+    ) -> anyhow::Result<Option<String>> {
+        Ok(linearizable::check(history)?.map(|counterexample| counterexample.to_string()))
+    }
+}
+
+/// Transactions of reads and writes of unique values on a few registers,
+/// checked for the anomalies `level` rules out.
+pub struct TxnRwRegister {
+    pub keys: u64,
+    pub level: Level,
+    next: u64,
+}
+
+impl Default for TxnRwRegister {
+    fn default() -> Self {
+        TxnRwRegister {
+            keys: 5,
+            level: Level::ReadCommitted,
+            next: 0,
+        }
+    }
+}
+
+impl Workload for TxnRwRegister {
+    fn request(&mut self, rng: &mut StdRng) -> Value {
+        let txn: Vec<Value> = (0..rng.gen_range(1..=4))
+            .map(|_| {
+                let key = rng.gen_range(0..self.keys);
+                if rng.gen_bool(0.5) {
+                    json!(["r", key, null])
+                } else {
+                    self.next += 1;
+                    json!(["w", key, self.next])
+                }
+            })
+            .collect();
+        json!({"type": "txn", "txn": txn})
+    }
+
+    fn check(
+        &self,
+        history: &History,
+        _: &[(String, Option<Value>)],
+    ) -> anyhow::Result<Option<String>> {
+        let report = txn::check(history, txn::Workload::RwRegister)?;
+        Ok(report
+            .violated()
+            .contains(&self.level)
+            .then(|| report.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(ops: &[(Value, EventKind, Value)]) -> History {
+        let mut history = History::default();
+        for (id, (request, kind, reply)) in ops.iter().enumerate() {
+            history.push(EventKind::Invoke, "c1".to_string(), id, request.clone());
+            history.push(*kind, "c1".to_string(), id, reply.clone());
+        }
+        history
+    }
+
+    #[test]
+    fn counters_allow_for_indeterminate_adds() {
+        let history = history(&[
+            (json!({"type": "add", "delta": 3}), EventKind::Ok, json!({})),
+            (
+                json!({"type": "add", "delta": -2}),
+                EventKind::Info,
+                json!({}),
+            ),
+            (
+                json!({"type": "add", "delta": 4}),
+                EventKind::Fail,
+                json!({}),
+            ),
+        ]);
+        let counter = Counter { negative: true };
+        let finals = |value: i64| vec![("n0".to_string(), Some(json!({"value": value})))];
+        assert_eq!(counter.check(&history, &finals(1)).unwrap(), None);
+        assert_eq!(counter.check(&history, &finals(3)).unwrap(), None);
+        assert!(counter.check(&history, &finals(7)).unwrap().is_some());
+        assert!(counter
+            .check(&history, &[("n0".to_string(), None)])
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn broadcasts_have_to_reach_every_node() {
+        let history = history(&[
+            (
+                json!({"type": "broadcast", "message": 1}),
+                EventKind::Ok,
+                json!({}),
+            ),
+            (
+                json!({"type": "broadcast", "message": 2}),
+                EventKind::Info,
+                json!({}),
+            ),
+        ]);
+        let broadcast = Broadcast::default();
+        let read = |messages: Value| Some(json!({"messages": messages}));
+        let finals = [
+            ("n0".to_string(), read(json!([1, 2]))),
+            ("n1".to_string(), read(json!([1]))),
+        ];
+        assert_eq!(broadcast.check(&history, &finals).unwrap(), None);
+
+        let finals = [("n0".to_string(), read(json!([2])))];
+        let problem = broadcast.check(&history, &finals).unwrap().unwrap();
+        assert_eq!(problem, "n0 never got 1");
+        let finals = [("n0".to_string(), read(json!([1, 3])))];
+        assert!(broadcast.check(&history, &finals).unwrap().is_some());
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt::{self, Display};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    context::NodeContext,
+    error::{Error, ErrorCode},
+    rpc::retry::RetryPolicy,
+};
+
+/// The key-value services maelstrom runs next to the nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Service {
+    /// Sequentially consistent, reads may be stale.
+    SeqKv,
+    /// Linearizable.
+    LinKv,
+    /// Last-writer-wins, replicas may diverge.
+    LwwKv,
+}
+
+impl Service {
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::SeqKv => "seq-kv",
+            Service::LinKv => "lin-kv",
+            Service::LwwKv => "lww-kv",
+        }
+    }
+
+    /// The service called `name`, as a `dest`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Service::SeqKv, Service::LinKv, Service::LwwKv]
+            .into_iter()
+            .find(|service| service.name() == name)
+    }
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum KvPayload {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+}
+
+/// Why a key-value operation failed.
+#[derive(Debug)]
+pub enum KvError {
+    KeyDoesNotExist,
+    PreconditionFailed,
+    /// Any other error reply, a timeout or a malformed reply.
+    Other(anyhow::Error),
+}
+
+impl Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::KeyDoesNotExist => f.write_str("key does not exist"),
+            KvError::PreconditionFailed => f.write_str("precondition failed"),
+            KvError::Other(error) => write!(f, "{:#}", error),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+impl From<anyhow::Error> for KvError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast_ref::<Error>().map(|e| e.code) {
+            Some(ErrorCode::KeyDoesNotExist) => KvError::KeyDoesNotExist,
+            Some(ErrorCode::PreconditionFailed) => KvError::PreconditionFailed,
+            _ => KvError::Other(error),
+        }
+    }
+}
+
+impl From<serde_json::Error> for KvError {
+    fn from(error: serde_json::Error) -> Self {
+        KvError::Other(error.into())
+    }
+}
+
+/// A blocking client for one of the key-value services.
+///
+/// Reads are retried according to `policy`. Writes and compare-and-sets are
+/// sent once with `policy.timeout`, since a resent one could be applied
+/// twice.
+#[derive(Debug, Clone, Copy)]
+pub struct KvClient {
+    pub service: Service,
+    pub policy: RetryPolicy,
+}
+
+impl KvClient {
+    pub fn new(service: Service) -> Self {
+        KvClient {
+            service,
+            policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn read<K, V, T>(&self, key: K, ctx: &mut NodeContext<T>) -> Result<V, KvError>
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+    {
+        let payload = KvPayload::Read {
+            key: serde_json::to_value(key)?,
+        };
+        match self.call(payload, self.policy, ctx)? {
+            KvPayload::ReadOk { value } => Ok(serde_json::from_value(value)?),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub fn write<K, V, T>(&self, key: K, value: V, ctx: &mut NodeContext<T>) -> Result<(), KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let payload = KvPayload::Write {
+            key: serde_json::to_value(key)?,
+            value: serde_json::to_value(value)?,
+        };
+        match self.call(payload, RetryPolicy::once(self.policy.timeout), ctx)? {
+            KvPayload::WriteOk => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Sets `key` to `to` if it currently holds `from`. With
+    /// `create_if_not_exists` a missing key is created with `to` instead of
+    /// failing with `KeyDoesNotExist`.
+    pub fn cas<K, V, T>(
+        &self,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+        ctx: &mut NodeContext<T>,
+    ) -> Result<(), KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let payload = KvPayload::Cas {
+            key: serde_json::to_value(key)?,
+            from: serde_json::to_value(from)?,
+            to: serde_json::to_value(to)?,
+            create_if_not_exists,
+        };
+        match self.call(payload, RetryPolicy::once(self.policy.timeout), ctx)? {
+            KvPayload::CasOk => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn call<T>(
+        &self,
+        payload: KvPayload,
+        policy: RetryPolicy,
+        ctx: &mut NodeContext<T>,
+    ) -> Result<KvPayload, KvError> {
+        let reply = ctx
+            .call::<_, KvPayload>(self.service.name(), payload, policy)?
+            .wait(ctx)?;
+        Ok(reply.body.payload)
+    }
+}
+
+fn unexpected(reply: KvPayload) -> KvError {
+    KvError::Other(anyhow::anyhow!("unexpected kv reply: {:?}", reply))
+}
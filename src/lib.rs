@@ -1,15 +1,34 @@
-pub mod utils;
+pub mod broadcast;
+pub mod check;
+pub mod clock;
+pub mod context;
+pub mod crdt;
+pub mod error;
+pub mod faults;
+pub mod gossip;
+pub mod harness;
+pub mod kv;
+pub mod log;
+pub mod paxos;
 pub mod rpc;
+pub mod sim;
+pub mod timers;
+pub mod topology;
+pub mod tso;
+pub mod txn;
+pub mod utils;
 use core::fmt::Debug;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 
 use std::{
-    io::{self, stdin, stdout, Write},
-    sync::{mpsc::channel, Mutex, Arc},
-    thread, time, rc::Rc, borrow::Cow, cell::RefCell,
+    io::{stdin, stdout, Write},
+    sync::mpsc::{channel, RecvTimeoutError},
 };
 
-use crate::utils::await_event::ExpectedMessages;
+use crate::context::NodeContext;
+use crate::error::{Error, ErrorCode};
+use crate::rpc::stdin_handler::{message_handler, Event};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message<P: Serialize + Debug> {
@@ -45,14 +64,51 @@ impl<P: Serialize + Debug> Message<P> {
         }
     }
 
-    pub fn send(&self, out: &mut impl Write) -> anyhow::Result<()> {
-        eprintln!("out: {:?}", self);
-        serde_json::to_writer(&mut *out, self)?;
+    pub fn reply_error(
+        &self,
+        code: ErrorCode,
+        text: impl Into<String>,
+        id: &mut usize,
+    ) -> Message<Error> {
+        self.reply(Error::new(code, text), id)
+    }
+
+    pub fn send(&self, out: &mut (impl Write + ?Sized)) -> anyhow::Result<()> {
+        let line = serde_json::to_string(self)?;
+        log::message("out", &line);
+        out.write_all(line.as_bytes())?;
         out.write_all(b"\n")?;
         Ok(())
     }
 }
 
+impl Message<Value> {
+    /// Parses the raw payload into `P`.
+    pub fn decode<P: DeserializeOwned + Serialize + Debug>(self) -> anyhow::Result<Message<P>> {
+        Ok(Message {
+            src: self.src,
+            dest: self.dest,
+            body: Body {
+                payload: serde_json::from_value(self.body.payload)?,
+                msg_id: self.body.msg_id,
+                in_reply_to: self.body.in_reply_to,
+            },
+        })
+    }
+
+    /// Parses a reply into `P`, or fails with the `Error` it carries if it is
+    /// an `error` message.
+    pub fn decode_reply<P: DeserializeOwned + Serialize + Debug>(
+        self,
+    ) -> anyhow::Result<Message<P>> {
+        if self.body.payload.get("type").and_then(Value::as_str) == Some("error") {
+            let error: Error = serde_json::from_value(self.body.payload)?;
+            return Err(error.into());
+        }
+        self.decode()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Body<P> {
     #[serde(flatten)]
@@ -75,71 +131,137 @@ pub struct Init {
     pub node_ids: Vec<String>,
 }
 
-pub trait Node<P: Serialize + Debug, InitState> {
-    fn new(state: InitState, init: Init) -> Self;
-    fn handle(&mut self, message: Message<P>, out: &mut impl Write) -> anyhow::Result<()>;
-    fn timed_call(&mut self, _out: &mut impl Write) -> anyhow::Result<()> {
+/// A node driven by `main_loop`. `T` is the event its timers deliver to
+/// `on_timer`.
+pub trait Node<P: Serialize + Debug, InitState, T = ()> {
+    fn new(state: InitState, ctx: &mut NodeContext<T>) -> Self;
+    fn handle(&mut self, message: Message<P>, ctx: &mut NodeContext<T>) -> anyhow::Result<()>;
+    fn on_timer(&mut self, _timer: T, _ctx: &mut NodeContext<T>) -> anyhow::Result<()> {
         Ok(())
     }
+
+    /// Gets every inbound message that does not parse as a `Message<P>`,
+    /// raw. By default requests are answered with `not-supported`, anything
+    /// else is dropped.
+    fn handle_unknown(&mut self, message: Value, ctx: &mut NodeContext<T>) -> anyhow::Result<()> {
+        let Ok(message) = serde_json::from_value::<Message<Value>>(message) else {
+            return Ok(());
+        };
+        // Answering a reply nobody waits for could start an error ping-pong
+        // with another node.
+        if message.body.msg_id.is_none() || message.body.in_reply_to.is_some() {
+            return Ok(());
+        }
+        let kind = message.body.payload.get("type").cloned();
+        ctx.reply_error(
+            &message,
+            ErrorCode::NotSupported,
+            format!("unsupported message type {}", kind.unwrap_or(Value::Null)),
+        )
+    }
 }
 
-//<'a, P: Deserialize<'a>, N: Node>
-pub fn main_loop<
-    'a,
-    S,
-    P: Deserialize<'a> + Debug + Clone + Serialize + Send + 'static,
-    N: Node<P, S>,
->(
-    state: S,
-) -> anyhow::Result<()> {
+pub fn main_loop<S, P, N, T>(state: S) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Debug + Clone + Serialize + Send + 'static,
+    N: Node<P, S, T>,
+    T: Clone + PartialEq + Debug,
+{
     let stdin = stdin();
-    let mut out = stdout().lock();
 
     // let init_msg = serde_json::from_str::<Message<InitPayload>>(r#"{"src": "1", "dest":"2", "body": {"type":     "init","msg_id":   1,"node_id":  "n3","node_ids": ["n1", "n2", "n3"]}}"#)?;
-    let init_msg =
-        serde_json::from_str::<Message<InitPayload>>(stdin.lines().next().unwrap()?.as_str())?;
-
-    eprintln!("in: {:?}", init_msg);
+    let line = stdin.lines().next().unwrap()?;
+    log::message("in", &line);
+    let init_msg = serde_json::from_str::<Message<InitPayload>>(&line)?;
 
     let InitPayload::Init(init) = init_msg.body.payload.clone() else {
-			panic!("wrong init msg: {:?}",init_msg);
-		};
-
-    let mut node = N::new(state, init);
+        panic!("wrong init msg: {:?}", init_msg);
+    };
+    log::set_node_id(&init.node_id);
 
-    init_msg
-        .reply(InitPayload::InitOk {}, &mut 1)
-        .send(&mut out)?;
+    let mut ctx = NodeContext::new(init, Box::new(stdout().lock()));
+    let mut node = N::new(state, &mut ctx);
+    ctx.reply(&init_msg, InitPayload::InitOk {})?;
 
-	let waited = Arc::new(Mutex::new(ExpectedMessages::default()));
-
-	let waited_reciver = waited.clone();
     let (sn, rw) = channel();
-    let thread_reader = thread::spawn(move || {
-        let stdin = io::stdin().lock();
-        for i in serde_json::Deserializer::from_reader(stdin).into_iter::<Message<P>>() {
-            let i = i.unwrap();
-            eprintln!("in: {:?}", i);
-			
-			let lock = waited_reciver.lock().unwrap();
-			if i.body.in_reply_to.is_some() && lock.msg_id.contains(&i.body.in_reply_to.unwrap()) {
-				lock.responses.push(i);
-			}
-            sn.send(i.clone()).unwrap();
+    let thread_reader = message_handler::<P>(sn, ctx.rpc.clone());
+
+    // Block on the channel until either a message arrives or the next timer
+    // or rpc deadline is due, so messages are handled as soon as they are read.
+    loop {
+        let event = match ctx.next_deadline() {
+            Some(deadline) => rw.recv_timeout(deadline.saturating_duration_since(ctx.now())),
+            None => rw.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match event {
+            Ok(event) => dispatch(&mut node, event, &mut ctx)?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
         }
-    });
+        tick(&mut node, &mut ctx)?;
+    }
 
-    for i in 0.. {
-        let val = rw.try_recv();
-        if val.is_ok() {
-            node.handle(val?, &mut out)?;
+    thread_reader
+        .join()
+        .map_err(|_| anyhow::anyhow!("stdin reader panicked"))?
+}
+
+/// Hands one inbound event to `node`.
+pub(crate) fn dispatch<P, S, N, T>(
+    node: &mut N,
+    event: Event<P>,
+    ctx: &mut NodeContext<T>,
+) -> anyhow::Result<()>
+where
+    P: Serialize + Debug,
+    N: Node<P, S, T>,
+{
+    match event {
+        Event::Message(message) => {
+            // A failing handler answers the request with an error instead
+            // of taking the node down.
+            let request = Message {
+                src: message.src.clone(),
+                dest: message.dest.clone(),
+                body: Body {
+                    payload: (),
+                    msg_id: message.body.msg_id,
+                    in_reply_to: message.body.in_reply_to,
+                },
+            };
+            if let Err(error) = node.handle(message, ctx) {
+                let error = Error::from(error);
+                warn!("failed to handle {:?}: {}", request, error);
+                // Like `handle_unknown`, never answer a reply, so two nodes
+                // cannot bounce errors back and forth.
+                if request.body.msg_id.is_some() && request.body.in_reply_to.is_none() {
+                    ctx.reply(&request, error)?;
+                }
+            }
         }
-        if i % 10 == 0 {
-            node.timed_call(&mut out)?;
+        Event::Reply(reply) => ctx.complete(reply)?,
+        Event::Unknown(message) => {
+            if let Err(error) = node.handle_unknown(message, ctx) {
+                warn!("failed to handle unknown message: {:#}", error);
+            }
         }
-        thread::sleep(time::Duration::from_millis(100));
-        if thread_reader.is_finished() && rw.try_recv().is_err() {
-            break;
+    }
+    Ok(())
+}
+
+/// Runs whatever is due on the clock of `ctx`: rpc retries and timeouts,
+/// then timers.
+pub(crate) fn tick<P, S, N, T>(node: &mut N, ctx: &mut NodeContext<T>) -> anyhow::Result<()>
+where
+    P: Serialize + Debug,
+    N: Node<P, S, T>,
+    T: Clone + PartialEq + Debug,
+{
+    let now = ctx.now();
+    ctx.expire(now)?;
+    while let Some(timer) = ctx.timers.pop_due(now) {
+        if let Err(error) = node.on_timer(timer.clone(), ctx) {
+            warn!("timer {:?} failed: {:#}", timer, error);
         }
     }
     Ok(())
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashSet,
+    env,
+    fmt::{self, Display},
+    io::Write,
+    str::FromStr,
+    sync::{OnceLock, RwLock},
+};
+
+use serde_json::Value;
+
+/// The environment variable holding the most verbose `Level` that is
+/// logged, `info` if unset.
+pub const LOG_LEVEL_ENV: &str = "LOG_LEVEL";
+
+/// The environment variable switching on the trace of every message the
+/// node reads and writes, see `MessageFilter`.
+pub const LOG_MESSAGES_ENV: &str = "LOG_MESSAGES";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(anyhow::anyhow!("unknown log level {:?}", s)),
+        }
+    }
+}
+
+/// Which messages are traced, parsed from a comma separated list of message
+/// types: `*` traces every type, a `!` in front of a type leaves it out, so
+/// `*,!gossip,!gossip_ok` traces everything but the gossip. An empty list
+/// traces nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageFilter {
+    all: bool,
+    include: HashSet<String>,
+    exclude: HashSet<String>,
+}
+
+impl MessageFilter {
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.include.is_empty()
+    }
+
+    pub fn matches(&self, kind: &str) -> bool {
+        !self.exclude.contains(kind) && (self.all || self.include.contains(kind))
+    }
+}
+
+impl FromStr for MessageFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = MessageFilter::default();
+        for kind in s.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            if matches!(kind, "*" | "1" | "all") {
+                filter.all = true;
+            } else if let Some(kind) = kind.strip_prefix('!') {
+                filter.exclude.insert(kind.to_string());
+            } else {
+                filter.include.insert(kind.to_string());
+            }
+        }
+        Ok(filter)
+    }
+}
+
+struct Config {
+    level: Level,
+    messages: MessageFilter,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+static NODE_ID: RwLock<String> = RwLock::new(String::new());
+
+/// Reads the configuration from the environment the first time it is needed.
+/// A value that does not parse is reported once and falls back to the
+/// default.
+fn config() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        let level = parse_env(LOG_LEVEL_ENV).unwrap_or(Level::Info);
+        let messages = parse_env(LOG_MESSAGES_ENV).unwrap_or_default();
+        Config { level, messages }
+    })
+}
+
+fn parse_env<V: FromStr<Err = anyhow::Error>>(var: &str) -> Option<V> {
+    let value = env::var(var).ok()?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(error) => {
+            eprintln!("ignoring {}: {:#}", var, error);
+            None
+        }
+    }
+}
+
+/// Sets the prefix of every line logged from now on, done by `main_loop`
+/// once `init` arrives and by the simulator before running each node.
+pub fn set_node_id(node_id: &str) {
+    let mut current = NODE_ID.write().unwrap();
+    if *current != node_id {
+        *current = node_id.to_string();
+    }
+}
+
+fn with_node_id<R>(f: impl FnOnce(&str) -> R) -> R {
+    let node_id = NODE_ID.read().unwrap();
+    f(if node_id.is_empty() { "-" } else { &node_id })
+}
+
+pub fn enabled(level: Level) -> bool {
+    level <= config().level
+}
+
+/// Writes one line to stderr, prefixed with the node id. Use the `error!`,
+/// `warn!`, `info!`, `debug!` and `trace!` macros instead.
+pub fn log(level: Level, args: fmt::Arguments<'_>) {
+    if !enabled(level) {
+        return;
+    }
+    with_node_id(|node_id| {
+        let _ = writeln!(
+            std::io::stderr().lock(),
+            "[{}] {:5} {}",
+            node_id,
+            level,
+            args
+        );
+    });
+}
+
+/// Traces a message read from (`in`) or written to (`out`) the network as
+/// its raw JSON `line`, if `LOG_MESSAGES` lets its type through.
+pub fn message(direction: &str, line: &str) {
+    let filter = &config().messages;
+    if filter.is_empty() {
+        return;
+    }
+    let value: Option<Value> = serde_json::from_str(line).ok();
+    let kind = value
+        .as_ref()
+        .and_then(|v| v.pointer("/body/type"))
+        .and_then(Value::as_str)
+        .unwrap_or("");
+    if filter.matches(kind) {
+        with_node_id(|node_id| {
+            let _ = writeln!(
+                std::io::stderr().lock(),
+                "[{}] {:>3}: {}",
+                node_id,
+                direction,
+                line
+            );
+        });
+    }
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Trace, format_args!($($arg)*)) };
+}
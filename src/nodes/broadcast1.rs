@@ -1,8 +1,6 @@
-mod db;
+use std::collections::HashMap;
 
-use std::{collections::HashMap, io::Write};
-
-use dist_system::{main_loop, Init, Message, Node};
+use dist_system::{context::NodeContext, main_loop, Message, Node};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -24,42 +22,34 @@ enum Payload {
 }
 
 struct BroadcastNode {
-    id: usize,
-    node_name: String,
     messages: Vec<usize>,
 }
 
 impl Node<Payload, ()> for BroadcastNode {
-    fn new(_state: (), init: Init) -> Self {
+    fn new(_state: (), _ctx: &mut NodeContext) -> Self {
         BroadcastNode {
-            id: 2,
-            node_name: init.node_id,
             messages: Vec::new(),
         }
     }
 
-    fn handle(&mut self, message: Message<Payload>, out: &mut impl Write) -> anyhow::Result<()> {
+    fn handle(&mut self, message: Message<Payload>, ctx: &mut NodeContext) -> anyhow::Result<()> {
         match &message.body.payload {
             Payload::Broadcast { message: data } => {
                 self.messages.push(*data);
-                message
-                    .reply(Payload::BroadcastOk, &mut self.id)
-                    .send(out)?;
+                ctx.reply(&message, Payload::BroadcastOk)?;
             }
             Payload::BroadcastOk => {}
             Payload::Read => {
-                message
-                    .reply(
-                        Payload::ReadOk {
-                            messages: self.messages.clone(),
-                        },
-                        &mut self.id,
-                    )
-                    .send(out)?;
+                ctx.reply(
+                    &message,
+                    Payload::ReadOk {
+                        messages: self.messages.clone(),
+                    },
+                )?;
             }
             Payload::ReadOk { messages: _ } => {}
             Payload::Topology { topology: _ } => {
-                message.reply(Payload::TopologyOk, &mut self.id).send(out)?;
+                ctx.reply(&message, Payload::TopologyOk)?;
             }
             Payload::TopologyOk => {}
         }
@@ -68,6 +58,6 @@ impl Node<Payload, ()> for BroadcastNode {
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), Payload, BroadcastNode>(())?;
+    main_loop::<(), Payload, BroadcastNode, ()>(())?;
     Ok(())
 }
@@ -1,6 +1,12 @@
-use std::{collections::HashMap, io::Write};
+use std::collections::HashMap;
 
-use dist_system::{main_loop, Init, Message, Node};
+use dist_system::{
+    context::NodeContext,
+    crdt::{Crdt, GSet},
+    main_loop,
+    topology::{Neighbours, Overlay},
+    Message, Node,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -20,88 +26,79 @@ enum Payload {
     },
     TopologyOk,
     Propogate {
-        message: usize,
-        id: usize,
+        messages: GSet<usize>,
     },
 }
 
 struct BroadcastNode {
-    id: usize,
-    count: usize,
-    node_name: String,
-    near_nodes: Vec<String>,
-    messages: Vec<usize>,
+    neighbours: Neighbours,
+    messages: GSet<usize>,
+}
+
+impl BroadcastNode {
+    /// Merges `messages` and floods what was new to every neighbour except
+    /// `from`. Values that were already seen stop here.
+    fn propogate(
+        &mut self,
+        messages: &GSet<usize>,
+        from: &str,
+        ctx: &mut NodeContext,
+    ) -> anyhow::Result<()> {
+        let mut delta = GSet::default();
+        for data in messages.iter() {
+            delta.merge(&self.messages.insert(*data));
+        }
+        if delta.is_empty() {
+            return Ok(());
+        }
+        for node in &self.neighbours.nodes {
+            if node == from {
+                continue;
+            }
+            ctx.send(
+                node.clone(),
+                Payload::Propogate {
+                    messages: delta.clone(),
+                },
+            )?;
+        }
+        Ok(())
+    }
 }
 
-impl Node<Payload, ()> for BroadcastNode {
-    fn new(_state: (), init: Init) -> Self {
+impl Node<Payload, Overlay> for BroadcastNode {
+    fn new(overlay: Overlay, ctx: &mut NodeContext) -> Self {
         BroadcastNode {
-            id: 2,
-            count: 0,
-            node_name: init.node_id,
-            near_nodes: init.node_ids,
-            messages: Vec::new(),
+            neighbours: Neighbours::new(overlay, &ctx.node_id, &ctx.node_ids),
+            messages: GSet::default(),
         }
     }
 
-    fn handle(&mut self, message: Message<Payload>, out: &mut impl Write) -> anyhow::Result<()> {
+    fn handle(&mut self, message: Message<Payload>, ctx: &mut NodeContext) -> anyhow::Result<()> {
         match &message.body.payload {
             Payload::Broadcast { message: data } => {
-                self.messages.push(*data);
-                self.count = self.messages.len();
-                for node in &self.near_nodes {
-                    let mut m = message.reply(
-                        Payload::Propogate {
-                            message: *data,
-                            id: self.count,
-                        },
-                        &mut self.id,
-                    );
-                    m.dest = node.clone();
-                    m.send(out)?;
-                }
-                message
-                    .reply(Payload::BroadcastOk, &mut self.id)
-                    .send(out)?;
+                let mut messages = GSet::default();
+                messages.insert(*data);
+                self.propogate(&messages, &message.src, ctx)?;
+                ctx.reply(&message, Payload::BroadcastOk)?;
             }
             Payload::BroadcastOk => {}
             Payload::Read => {
-                message
-                    .reply(
-                        Payload::ReadOk {
-                            messages: self.messages.clone(),
-                        },
-                        &mut self.id,
-                    )
-                    .send(out)?;
+                ctx.reply(
+                    &message,
+                    Payload::ReadOk {
+                        messages: self.messages.iter().copied().collect(),
+                    },
+                )?;
             }
             Payload::ReadOk { messages: _ } => {}
-            Payload::Topology { topology: _ } => {
-                message.reply(Payload::TopologyOk, &mut self.id).send(out)?;
+            Payload::Topology { topology } => {
+                self.neighbours.on_topology(topology);
+                ctx.reply(&message, Payload::TopologyOk)?;
             }
             Payload::TopologyOk => {}
-            Payload::Propogate { message: data, id } => {
-                eprintln!("something");
-                if *id <= self.count {
-                    return Ok(());
-                }
-
-                self.messages.push(*data);
-                self.count = self.messages.len();
-                for node in &self.near_nodes {
-                    let mut m = message.reply(
-                        Payload::Propogate {
-                            message: *data,
-                            id: self.count,
-                        },
-                        &mut self.id,
-                    );
-                    m.dest = node.clone();
-                    m.send(out)?;
-                }
-                message
-                    .reply(Payload::BroadcastOk, &mut self.id)
-                    .send(out)?;
+            Payload::Propogate { messages } => {
+                self.propogate(messages, &message.src, ctx)?;
             }
         }
         Ok(())
@@ -109,6 +106,6 @@ impl Node<Payload, ()> for BroadcastNode {
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), Payload, BroadcastNode>(())?;
+    main_loop::<Overlay, Payload, BroadcastNode, ()>(Overlay::from_env()?)?;
     Ok(())
 }
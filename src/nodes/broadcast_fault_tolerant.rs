@@ -0,0 +1,165 @@
+use std::{collections::HashMap, time::Duration};
+
+use dist_system::{
+    context::NodeContext,
+    crdt::GSet,
+    gossip::{DeltaSync, GossipConfig},
+    main_loop,
+    topology::{Neighbours, Overlay},
+    Message, Node,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum Payload {
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    ReadOk {
+        messages: Vec<usize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+    Gossip {
+        messages: GSet<usize>,
+        upto: u64,
+    },
+    GossipOk {
+        upto: u64,
+    },
+    /// One new delta, sent as soon as it is applied.
+    Delta {
+        messages: GSet<usize>,
+        number: u64,
+    },
+    DeltaOk {
+        number: u64,
+    },
+}
+
+/// How often values that were not acknowledged yet are resent, unless
+/// `GOSSIP_INTERVAL_MS` says otherwise.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Timer {
+    Retry,
+}
+
+/// Sends every change to the neighbours right away, and on every
+/// `Timer::Retry` resends whatever a neighbour has not acknowledged, so
+/// values sent into a partition arrive once it heals.
+struct BroadcastNode {
+    neighbours: Neighbours,
+    messages: DeltaSync<GSet<usize>>,
+}
+
+impl BroadcastNode {
+    /// Sends the delta just applied to every neighbour.
+    fn send_delta(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        let Some((number, delta)) = self.messages.last_delta() else {
+            return Ok(());
+        };
+        let delta = Payload::Delta {
+            messages: delta.clone(),
+            number,
+        };
+        for node in self.neighbours.nodes.clone() {
+            ctx.send(node, delta.clone())?;
+        }
+        Ok(())
+    }
+
+    fn gossip(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        for (node, upto, messages) in self.messages.batches(ctx.rng()) {
+            ctx.send(node, Payload::Gossip { messages, upto })?;
+        }
+        Ok(())
+    }
+}
+
+impl Node<Payload, (Overlay, GossipConfig), Timer> for BroadcastNode {
+    fn new((overlay, config): (Overlay, GossipConfig), ctx: &mut NodeContext<Timer>) -> Self {
+        ctx.timers.every(Timer::Retry, config.interval);
+        let neighbours = Neighbours::new(overlay, &ctx.node_id, &ctx.node_ids);
+        BroadcastNode {
+            messages: DeltaSync::new(config, neighbours.nodes.clone()),
+            neighbours,
+        }
+    }
+
+    fn handle(
+        &mut self,
+        message: Message<Payload>,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
+        match &message.body.payload {
+            Payload::Broadcast { message: data } => {
+                if !self.messages.state().contains(data) {
+                    self.messages.mutate(|messages| messages.insert(*data));
+                    self.send_delta(ctx)?;
+                }
+                ctx.reply(&message, Payload::BroadcastOk)?;
+            }
+            Payload::BroadcastOk => {}
+            Payload::Read => {
+                ctx.reply(
+                    &message,
+                    Payload::ReadOk {
+                        messages: self.messages.state().iter().copied().collect(),
+                    },
+                )?;
+            }
+            Payload::ReadOk { messages: _ } => {}
+            Payload::Topology { topology } => {
+                self.neighbours.on_topology(topology);
+                self.messages.set_peers(self.neighbours.nodes.clone());
+                ctx.reply(&message, Payload::TopologyOk)?;
+            }
+            Payload::TopologyOk => {}
+            Payload::Gossip { messages, upto } => {
+                if self.messages.receive(messages.clone()) {
+                    self.send_delta(ctx)?;
+                }
+                ctx.reply(&message, Payload::GossipOk { upto: *upto })?;
+            }
+            Payload::GossipOk { upto } => {
+                self.messages.ack(&message.src, *upto);
+            }
+            Payload::Delta { messages, number } => {
+                if self.messages.receive(messages.clone()) {
+                    self.send_delta(ctx)?;
+                }
+                ctx.reply(&message, Payload::DeltaOk { number: *number })?;
+            }
+            Payload::DeltaOk { number } => {
+                self.messages.ack_delta(&message.src, *number);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_timer(&mut self, timer: Timer, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        match timer {
+            Timer::Retry => self.gossip(ctx),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let config = GossipConfig::from_env_or(GossipConfig {
+        interval: RETRY_INTERVAL,
+        fanout: None,
+    })?;
+    main_loop::<(Overlay, GossipConfig), Payload, BroadcastNode, Timer>((
+        Overlay::from_env()?,
+        config,
+    ))?;
+    Ok(())
+}
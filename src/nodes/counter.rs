@@ -1,110 +1,180 @@
-use std::{
-    io::Write,
+use dist_system::{
+    context::NodeContext,
+    crdt::GCounter,
+    gossip::{DeltaSync, GossipConfig},
+    main_loop, Message, Node,
 };
-
-use dist_system::{main_loop, utils::merge_messages, Init, Message, Node};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 enum Payload {
     Read,
-    ReadOk { value: usize },
-    Add { delta: usize },
+    ReadOk { value: u64 },
+    Add { delta: u64 },
     AddOk,
-    Propogate { messages: Vec<PropogateInfo> },
-    PropogateOk,
+    Propogate { counter: GCounter, upto: u64 },
+    PropogateOk { upto: u64 },
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
-struct PropogateInfo {
-    delta: usize,
-    uuid: String,
+#[derive(Debug, Clone, PartialEq)]
+enum Timer {
+    Gossip,
 }
 
 struct CounterNode {
-    id: usize,
-    node_name: String,
-    near_nodes: Vec<String>,
-    counter: usize,
-    messages: Vec<PropogateInfo>,
+    counter: DeltaSync<GCounter>,
 }
 
 impl CounterNode {
-    fn propogate(&mut self, out: &mut impl Write) -> anyhow::Result<()> {
-        for node in self.near_nodes.clone() {
-            Message::new(
-                self.node_name.clone(),
-                node.clone(),
-                Payload::Propogate {
-                    messages: self.messages.clone(),
-                },
-                &mut self.id,
-            )
-            .send(out)?;
+    fn propogate(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        for (node, upto, counter) in self.counter.batches(ctx.rng()) {
+            ctx.send(node, Payload::Propogate { counter, upto })?;
         }
         Ok(())
     }
 }
 
-impl Node<Payload, ()> for CounterNode {
-    fn new(_state: (), init: Init) -> Self {
+impl Node<Payload, GossipConfig, Timer> for CounterNode {
+    fn new(config: GossipConfig, ctx: &mut NodeContext<Timer>) -> Self {
+        ctx.timers.every(Timer::Gossip, config.interval);
         CounterNode {
-            id: 2,
-            counter: 0,
-            node_name: init.node_id.clone(),
-            near_nodes: {
-                let mut m = init.node_ids;
-                m.retain(|x| *x != init.node_id);
-                m
-            },
-            messages: Vec::new(),
+            counter: DeltaSync::new(config, ctx.peers().cloned().collect()),
         }
     }
 
-    fn handle(&mut self, message: Message<Payload>, out: &mut impl Write) -> anyhow::Result<()> {
+    fn handle(
+        &mut self,
+        message: Message<Payload>,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
         match &message.body.payload {
             Payload::Add { delta } => {
-                self.messages.push(PropogateInfo {
-                    delta: *delta,
-                    uuid: Uuid::new_v4().to_string(),
-                });
-                self.counter += *delta;
-                message.reply(Payload::AddOk, &mut self.id).send(out)?;
+                self.counter
+                    .mutate(|counter| counter.increment(&ctx.node_id, *delta));
+                ctx.reply(&message, Payload::AddOk)?;
             }
             Payload::AddOk => {}
             Payload::Read => {
-                message
-                    .reply(
-                        Payload::ReadOk {
-                            value: self.counter,
-                        },
-                        &mut self.id,
-                    )
-                    .send(out)?;
+                ctx.reply(
+                    &message,
+                    Payload::ReadOk {
+                        value: self.counter.state().value(),
+                    },
+                )?;
             }
             Payload::ReadOk { value: _ } => {}
-            Payload::Propogate { messages } => {
-                self.messages = merge_messages(self.messages.clone(), (*messages).clone());
-                self.counter = self.messages.iter().map(|x| x.delta).sum::<usize>();
-                message
-                    .reply(Payload::PropogateOk, &mut self.id)
-                    .send(out)?;
+            Payload::Propogate { counter, upto } => {
+                self.counter.receive(counter.clone());
+                ctx.reply(&message, Payload::PropogateOk { upto: *upto })?;
+            }
+            Payload::PropogateOk { upto } => {
+                self.counter.ack(&message.src, *upto);
             }
-            Payload::PropogateOk => {}
         }
         Ok(())
     }
 
-    fn timed_call(&mut self, out: &mut impl Write) -> anyhow::Result<()> {
-        self.propogate(out)?;
-        Ok(())
+    fn on_timer(&mut self, timer: Timer, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        match timer {
+            Timer::Gossip => self.propogate(ctx),
+        }
     }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), Payload, CounterNode>(())?;
+    main_loop::<GossipConfig, Payload, CounterNode, Timer>(GossipConfig::from_env()?)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use dist_system::{faults::Timeline, sim::Simulation};
+    use serde_json::Value;
+
+    use super::*;
+
+    type CounterSim = Simulation<GossipConfig, Payload, CounterNode, Timer>;
+
+    fn counters(nodes: usize, seed: u64) -> CounterSim {
+        let config = GossipConfig {
+            interval: Duration::from_millis(50),
+            fanout: Some(1),
+        };
+        CounterSim::new(nodes, seed, config).unwrap()
+    }
+
+    fn read(sim: &mut CounterSim, node_id: &str) -> Value {
+        let reply = sim.call(node_id, Payload::Read, Duration::from_millis(100));
+        reply.unwrap().expect("no reply to read").body.payload["value"].clone()
+    }
+
+    fn simulate(seed: u64) -> CounterSim {
+        let mut sim = counters(3, seed);
+        for (i, node_id) in ["n0", "n1", "n2", "n0"].into_iter().enumerate() {
+            sim.request(
+                node_id,
+                Payload::Add {
+                    delta: i as u64 + 1,
+                },
+            )
+            .unwrap();
+            sim.run_for(Duration::from_millis(20)).unwrap();
+        }
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        sim
+    }
+
+    #[test]
+    fn nodes_converge_on_virtual_time() {
+        let mut sim = simulate(7);
+        assert_eq!(sim.elapsed(), Duration::from_millis(1080));
+        for node_id in ["n0", "n1", "n2"] {
+            assert_eq!(read(&mut sim, node_id), 10, "on {}", node_id);
+        }
+    }
+
+    #[test]
+    fn a_seed_replays_the_same_run() {
+        assert_eq!(simulate(1).journal(), simulate(1).journal());
+        assert_ne!(simulate(1).journal(), simulate(2).journal());
+    }
+
+    #[test]
+    fn partitions_hold_back_updates_until_they_heal() {
+        let mut sim = counters(3, 11);
+        sim.set_faults("partition {n0}|{n1,n2} from 0s to 2s".parse().unwrap());
+        sim.request("n0", Payload::Add { delta: 5 }).unwrap();
+        sim.request("n1", Payload::Add { delta: 1 }).unwrap();
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_eq!(read(&mut sim, "n0"), 5);
+        assert_eq!(read(&mut sim, "n2"), 1);
+
+        sim.run_for(Duration::from_secs(2)).unwrap();
+        for node_id in ["n0", "n1", "n2"] {
+            assert_eq!(read(&mut sim, node_id), 6, "on {}", node_id);
+        }
+    }
+
+    #[test]
+    fn lossy_networks_still_converge() {
+        let mut sim = counters(5, 3);
+        sim.set_faults(
+            "drop 0.3; duplicate 0.2; reorder 0.5 100ms; latency exp 20ms; partition bridge to 1s"
+                .parse()
+                .unwrap(),
+        );
+        for (i, node_id) in ["n0", "n1", "n2", "n3", "n4"].into_iter().enumerate() {
+            sim.request(node_id, Payload::Add { delta: 1 << i })
+                .unwrap();
+        }
+        sim.run_for(Duration::from_secs(5)).unwrap();
+        sim.set_faults(Timeline::default());
+        for node_id in ["n0", "n1", "n2", "n3", "n4"] {
+            assert_eq!(read(&mut sim, node_id), 31, "on {}", node_id);
+        }
+    }
+}
@@ -1,10 +1,11 @@
-use std::{
-    io::Write,
+use dist_system::{
+    context::NodeContext,
+    kv::{KvClient, KvError, Service},
+    main_loop, Message, Node,
 };
-
-use dist_system::{main_loop, utils::merge_messages, Init, Message, Node};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+
+const COUNTER_KEY: &str = "counter";
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -14,97 +15,68 @@ enum Payload {
     ReadOk { value: usize },
     Add { delta: usize },
     AddOk,
-    Propogate { messages: Vec<PropogateInfo> },
-    PropogateOk,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
-struct PropogateInfo {
-    delta: usize,
-    uuid: String,
 }
 
 struct CounterNode {
-    id: usize,
-    node_name: String,
-    near_nodes: Vec<String>,
-    counter: usize,
-    messages: Vec<PropogateInfo>,
+    kv: KvClient,
 }
 
 impl CounterNode {
-    fn propogate(&mut self, out: &mut impl Write) -> anyhow::Result<()> {
-        for node in self.near_nodes.clone() {
-            Message::new(
-                self.node_name.clone(),
-                node.clone(),
-                Payload::Propogate {
-                    messages: self.messages.clone(),
-                },
-                &mut self.id,
-            )
-            .send(out)?;
+    fn current(&mut self, ctx: &mut NodeContext) -> Result<usize, KvError> {
+        match self.kv.read(COUNTER_KEY, ctx) {
+            Err(KvError::KeyDoesNotExist) => Ok(0),
+            value => value,
+        }
+    }
+
+    /// seq-kv may serve a stale value, but a compare-and-set of the value
+    /// onto itself only succeeds on the latest one.
+    fn read(&mut self, ctx: &mut NodeContext) -> Result<usize, KvError> {
+        loop {
+            let value = self.current(ctx)?;
+            match self.kv.cas(COUNTER_KEY, value, value, true, ctx) {
+                Err(KvError::PreconditionFailed) => continue,
+                result => return result.map(|_| value),
+            }
+        }
+    }
+
+    fn add(&mut self, delta: usize, ctx: &mut NodeContext) -> Result<(), KvError> {
+        loop {
+            let value = self.current(ctx)?;
+            match self.kv.cas(COUNTER_KEY, value, value + delta, true, ctx) {
+                Err(KvError::PreconditionFailed) => continue,
+                result => return result,
+            }
         }
-        Ok(())
     }
 }
 
 impl Node<Payload, ()> for CounterNode {
-    fn new(_state: (), init: Init) -> Self {
+    fn new(_state: (), _ctx: &mut NodeContext) -> Self {
         CounterNode {
-            id: 2,
-            counter: 0,
-            node_name: init.node_id.clone(),
-            near_nodes: {
-                let mut m = init.node_ids;
-                m.retain(|x| *x != init.node_id);
-                m
-            },
-            messages: Vec::new(),
+            kv: KvClient::new(Service::SeqKv),
         }
     }
 
-    fn handle(&mut self, message: Message<Payload>, out: &mut impl Write) -> anyhow::Result<()> {
+    fn handle(&mut self, message: Message<Payload>, ctx: &mut NodeContext) -> anyhow::Result<()> {
         match &message.body.payload {
             Payload::Add { delta } => {
-                self.messages.push(PropogateInfo {
-                    delta: *delta,
-                    uuid: Uuid::new_v4().to_string(),
-                });
-                self.counter += *delta;
-                message.reply(Payload::AddOk, &mut self.id).send(out)?;
+                self.add(*delta, ctx)?;
+                ctx.reply(&message, Payload::AddOk)?;
             }
             Payload::AddOk => {}
             Payload::Read => {
-                message
-                    .reply(
-                        Payload::ReadOk {
-                            value: self.counter,
-                        },
-                        &mut self.id,
-                    )
-                    .send(out)?;
+                let value = self.read(ctx)?;
+                ctx.reply(&message, Payload::ReadOk { value })?;
             }
             Payload::ReadOk { value: _ } => {}
-            Payload::Propogate { messages } => {
-                self.messages = merge_messages(self.messages.clone(), (*messages).clone());
-                self.counter = self.messages.iter().map(|x| x.delta).sum::<usize>();
-                message
-                    .reply(Payload::PropogateOk, &mut self.id)
-                    .send(out)?;
-            }
-            Payload::PropogateOk => {}
         }
         Ok(())
     }
-
-    fn timed_call(&mut self, out: &mut impl Write) -> anyhow::Result<()> {
-        self.propogate(out)?;
-        Ok(())
-    }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), Payload, CounterNode>(())?;
+    main_loop::<(), Payload, CounterNode, ()>(())?;
     Ok(())
 }
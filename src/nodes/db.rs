@@ -1,12 +0,0 @@
-use dist_system::Message;
-
-
-
-
-pub struct SeqKV {}
-
-impl SeqKV {
-	fn ReadInt(name: String) -> usize {
-		Message::new(src, "seq-kv", , id)
-	}
-}
\ No newline at end of file
@@ -1,6 +1,4 @@
-use std::io::Write;
-
-use dist_system::{main_loop, Init, Message, Node};
+use dist_system::{context::NodeContext, main_loop, Message, Node};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -11,26 +9,22 @@ enum Payload {
     EchoOk { echo: String },
 }
 
-struct EchoNode {
-    id: usize,
-}
+struct EchoNode;
 
 impl Node<Payload, ()> for EchoNode {
-    fn new(_state: (), _init: Init) -> Self {
-        EchoNode { id: 2 }
+    fn new(_state: (), _ctx: &mut NodeContext) -> Self {
+        EchoNode
     }
 
-    fn handle(&mut self, message: Message<Payload>, out: &mut impl Write) -> anyhow::Result<()> {
+    fn handle(&mut self, message: Message<Payload>, ctx: &mut NodeContext) -> anyhow::Result<()> {
         match &message.body.payload {
             Payload::Echo { echo } => {
-                message
-                    .reply(
-                        Payload::EchoOk {
-                            echo: echo.to_string(),
-                        },
-                        &mut self.id,
-                    )
-                    .send(out)?;
+                ctx.reply(
+                    &message,
+                    Payload::EchoOk {
+                        echo: echo.to_string(),
+                    },
+                )?;
             }
             Payload::EchoOk { echo: _ } => {}
         }
@@ -39,6 +33,6 @@ impl Node<Payload, ()> for EchoNode {
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), Payload, EchoNode>(())?;
+    main_loop::<(), Payload, EchoNode, ()>(())?;
     Ok(())
 }
@@ -1,8 +1,11 @@
-use std::{collections::HashMap, io::Write};
+use std::collections::HashMap;
 
-use dist_system::{main_loop, utils::merge_messages, Init, Message, Node};
+use dist_system::{
+    context::NodeContext,
+    kv::{KvClient, KvError, Service},
+    main_loop, Message, Node,
+};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -31,81 +34,274 @@ enum Payload {
     ListCommittedOffsetsOk {
         offsets: HashMap<String, usize>,
     },
-    Propogate {
-        messages: Vec<PropogateInfo>,
-    },
-    PropogateOk,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
-struct PropogateInfo {
-    delta: usize,
-    uuid: String,
+/// Keeps every log in lin-kv as one entry per offset under
+/// `entry_<key>_<offset>`. A producer claims an offset by creating its entry
+/// with a compare-and-set that fails if the entry already exists, so an
+/// offset is taken exactly when its message is stored and a log never has
+/// holes, whatever happens to the reply. `next_<key>` only hints where the
+/// log ends, so producers do not have to scan it from the start.
+struct KafkaLog {
+    kv: KvClient,
 }
 
-struct KafkaLog {
-    id: usize,
-    node_name: String,
-    near_nodes: Vec<String>,
-    counter: usize,
-    messages: Vec<PropogateInfo>,
+fn next_key(key: &str) -> String {
+    format!("next_{}", key)
+}
+
+fn entry_key(key: &str, offset: usize) -> String {
+    format!("entry_{}_{}", key, offset)
+}
+
+fn commit_key(key: &str) -> String {
+    format!("commit_{}", key)
 }
 
 impl KafkaLog {
-    fn propogate(&mut self, out: &mut impl Write) -> anyhow::Result<()> {
-        for node in self.near_nodes.clone() {
-            Message::new(
-                self.node_name.clone(),
-                node.clone(),
-                Payload::Propogate {
-                    messages: self.messages.clone(),
-                },
-                &mut self.id,
-            )
-            .send(out)?;
+    /// An offset at or below the end of the log of `key`.
+    fn hint(&mut self, key: &str, ctx: &mut NodeContext) -> Result<usize, KvError> {
+        match self.kv.read(next_key(key), ctx) {
+            Err(KvError::KeyDoesNotExist) => Ok(0),
+            hint => hint,
+        }
+    }
+
+    /// Moves the hint of `key` up to `len`. Gives up on any error, a stale
+    /// hint only costs the next producer a few more tries.
+    fn advance_hint(&mut self, key: &str, len: usize, ctx: &mut NodeContext) {
+        while let Ok(hint) = self.hint(key, ctx) {
+            if hint >= len {
+                return;
+            }
+            match self.kv.cas(next_key(key), hint, len, hint == 0, ctx) {
+                Err(KvError::PreconditionFailed) => continue,
+                _ => return,
+            }
+        }
+    }
+
+    fn append(&mut self, key: &str, msg: usize, ctx: &mut NodeContext) -> Result<usize, KvError> {
+        let mut offset = self.hint(key, ctx)?;
+        loop {
+            match self
+                .kv
+                .cas(entry_key(key, offset), None, Some(msg), true, ctx)
+            {
+                Err(KvError::PreconditionFailed) => offset += 1,
+                result => break result?,
+            }
+        }
+        self.advance_hint(key, offset + 1, ctx);
+        Ok(offset)
+    }
+
+    /// The entries of `key` from offset `from` up to the end of the log.
+    fn entries(
+        &mut self,
+        key: &str,
+        from: usize,
+        ctx: &mut NodeContext,
+    ) -> Result<Vec<[usize; 2]>, KvError> {
+        let mut entries = Vec::new();
+        for offset in from.. {
+            match self.kv.read(entry_key(key, offset), ctx) {
+                Ok(msg) => entries.push([offset, msg]),
+                Err(KvError::KeyDoesNotExist) => break,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(entries)
+    }
+
+    fn committed(&mut self, key: &str, ctx: &mut NodeContext) -> Result<Option<usize>, KvError> {
+        match self.kv.read(commit_key(key), ctx) {
+            Err(KvError::KeyDoesNotExist) => Ok(None),
+            offset => offset.map(Some),
+        }
+    }
+
+    /// Never moves a committed offset backwards.
+    fn commit(&mut self, key: &str, offset: usize, ctx: &mut NodeContext) -> Result<(), KvError> {
+        loop {
+            let committed = self.committed(key, ctx)?;
+            if committed.is_some_and(|committed| committed >= offset) {
+                return Ok(());
+            }
+            match self.kv.cas(
+                commit_key(key),
+                committed.unwrap_or(0),
+                offset,
+                committed.is_none(),
+                ctx,
+            ) {
+                Err(KvError::PreconditionFailed) => continue,
+                result => return result,
+            }
         }
-        Ok(())
     }
 }
 
 impl Node<Payload, ()> for KafkaLog {
-    fn new(_state: (), init: Init) -> Self {
+    fn new(_state: (), _ctx: &mut NodeContext) -> Self {
         KafkaLog {
-            id: 2,
-            counter: 0,
-            node_name: init.node_id.clone(),
-            near_nodes: {
-                let mut m = init.node_ids;
-                m.retain(|x| *x != init.node_id);
-                m
-            },
-            messages: Vec::new(),
+            kv: KvClient::new(Service::LinKv),
         }
     }
 
-    fn handle(&mut self, message: Message<Payload>, out: &mut impl Write) -> anyhow::Result<()> {
+    fn handle(&mut self, message: Message<Payload>, ctx: &mut NodeContext) -> anyhow::Result<()> {
         match &message.body.payload {
-            Payload::Send { key, msg } => todo!(),
-            Payload::SendOk { offset } => todo!(),
-            Payload::Poll { offsets } => todo!(),
-            Payload::PollOk { msgs } => todo!(),
-            Payload::CommitOffsets { offsets } => todo!(),
-            Payload::CommitOffsetsOk => todo!(),
-            Payload::ListCommittedOffsets { keys } => todo!(),
-            Payload::ListCommittedOffsetsOk { offsets } => todo!(),
-            Payload::Propogate { messages } => todo!(),
-            Payload::PropogateOk => todo!(),
+            Payload::Send { key, msg } => {
+                let offset = self.append(key, *msg, ctx)?;
+                ctx.reply(&message, Payload::SendOk { offset })?;
+            }
+            Payload::SendOk { offset: _ } => {}
+            Payload::Poll { offsets } => {
+                let mut msgs = HashMap::new();
+                for (key, from) in offsets {
+                    msgs.insert(key.clone(), self.entries(key, *from, ctx)?);
+                }
+                ctx.reply(&message, Payload::PollOk { msgs })?;
+            }
+            Payload::PollOk { msgs: _ } => {}
+            Payload::CommitOffsets { offsets } => {
+                for (key, offset) in offsets {
+                    self.commit(key, *offset, ctx)?;
+                }
+                ctx.reply(&message, Payload::CommitOffsetsOk)?;
+            }
+            Payload::CommitOffsetsOk => {}
+            Payload::ListCommittedOffsets { keys } => {
+                let mut offsets = HashMap::new();
+                for key in keys {
+                    if let Some(offset) = self.committed(key, ctx)? {
+                        offsets.insert(key.clone(), offset);
+                    }
+                }
+                ctx.reply(&message, Payload::ListCommittedOffsetsOk { offsets })?;
+            }
+            Payload::ListCommittedOffsetsOk { offsets: _ } => {}
         }
         Ok(())
     }
-
-    fn timed_call(&mut self, out: &mut impl Write) -> anyhow::Result<()> {
-        self.propogate(out)?;
-        Ok(())
-    }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), Payload, KafkaLog>(())?;
+    main_loop::<(), Payload, KafkaLog, ()>(())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use dist_system::{clock::Clock, harness::Services, rpc::client::Rpc, Init};
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Answers lin-kv requests the moment the node writes them, so its
+    /// blocking kv calls find their reply waiting on a virtual clock. The
+    /// next request matching `lose_request`, say `"cas next_a"`, is lost, and
+    /// so is the reply to the next one matching `lose_reply`, after lin-kv
+    /// applied it.
+    #[derive(Default)]
+    struct LinKv {
+        services: Services,
+        rpc: Option<Rpc>,
+        line: Vec<u8>,
+        unanswered: Vec<Message<Value>>,
+        lose_request: Option<String>,
+        lose_reply: Option<String>,
+    }
+
+    #[derive(Clone, Default)]
+    struct Wire(Arc<Mutex<LinKv>>);
+
+    impl Write for Wire {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut kv = self.0.lock().unwrap();
+            kv.line.extend_from_slice(buf);
+            while let Some(end) = kv.line.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = kv.line.drain(..=end).collect();
+                let message: Message<Value> = serde_json::from_slice(&line)?;
+                kv.deliver(message);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl LinKv {
+        fn deliver(&mut self, request: Message<Value>) {
+            let payload = &request.body.payload;
+            let key = format!(
+                "{} {}",
+                payload["type"].as_str().unwrap_or(""),
+                payload["key"].as_str().unwrap_or("")
+            );
+            let lose = |rule: &mut Option<String>| rule.take_if(|k| *k == key).is_some();
+            if lose(&mut self.lose_request) {
+                return;
+            }
+            match self
+                .services
+                .handle(&request, &mut rand::thread_rng())
+                .unwrap()
+            {
+                Some(_) if lose(&mut self.lose_reply) => {}
+                Some(reply) => {
+                    self.rpc.as_ref().unwrap().route(reply);
+                }
+                None => self.unanswered.push(request),
+            }
+        }
+    }
+
+    fn node() -> (KafkaLog, NodeContext, Wire) {
+        let init = Init {
+            node_id: "n0".to_string(),
+            node_ids: vec!["n0".to_string()],
+        };
+        let wire = Wire::default();
+        let clock = Clock::starting_at(Instant::now());
+        let rng = StdRng::seed_from_u64(0);
+        let mut ctx = NodeContext::with_clock(init, Box::new(wire.clone()), clock, rng);
+        wire.0.lock().unwrap().rpc = Some(ctx.rpc.clone());
+        (KafkaLog::new((), &mut ctx), ctx, wire)
+    }
+
+    fn send(log: &mut KafkaLog, ctx: &mut NodeContext, msg: usize) -> anyhow::Result<usize> {
+        Ok(log.append("a", msg, ctx)?)
+    }
+
+    #[test]
+    fn lost_replies_leave_no_holes() {
+        let (mut log, mut ctx, wire) = node();
+        assert_eq!(send(&mut log, &mut ctx, 10).unwrap(), 0);
+
+        // The entry is stored but its producer never hears of it, and the
+        // hint is not moved past it.
+        wire.0.lock().unwrap().lose_reply = Some(format!("cas {}", entry_key("a", 1)));
+        assert!(send(&mut log, &mut ctx, 11).is_err());
+        wire.0.lock().unwrap().lose_request = Some(format!("cas {}", next_key("a")));
+        assert_eq!(send(&mut log, &mut ctx, 12).unwrap(), 2);
+
+        // The entry never gets stored, so its offset goes to the next one.
+        wire.0.lock().unwrap().lose_request = Some(format!("cas {}", entry_key("a", 3)));
+        assert!(send(&mut log, &mut ctx, 13).is_err());
+        assert_eq!(send(&mut log, &mut ctx, 14).unwrap(), 3);
+
+        let entries = log.entries("a", 1, &mut ctx).unwrap();
+        assert_eq!(entries, [[1, 11], [2, 12], [3, 14]]);
+        assert!(wire.0.lock().unwrap().unanswered.is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+use dist_system::{
+    error::{Error, ErrorCode},
+    main_loop,
+    paxos::{Paxos, PaxosMessage, PaxosTimer, StateMachine},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum Request {
+    Read,
+    Add { delta: i64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
+enum Response {
+    ReadOk { value: i64 },
+    AddOk,
+}
+
+/// A counter, as served to the `g-counter` and `pn-counter` workloads.
+#[derive(Default)]
+struct Counter {
+    value: i64,
+}
+
+impl StateMachine for Counter {
+    type Request = Request;
+    type Response = Response;
+
+    fn apply(&mut self, request: &Request) -> Result<Response, Error> {
+        match request {
+            Request::Read => Ok(Response::ReadOk { value: self.value }),
+            Request::Add { delta } => {
+                self.value = self.value.checked_add(*delta).ok_or_else(|| {
+                    Error::new(ErrorCode::MalformedRequest, "counter would overflow")
+                })?;
+                Ok(Response::AddOk)
+            }
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<(), PaxosMessage<Request>, Paxos<Counter>, PaxosTimer>(())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use dist_system::{
+        clock::Clock,
+        context::NodeContext,
+        paxos::{Ballot, Decided, PaxosPayload},
+        sim::Simulation,
+        Init, Message, Node,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::Value;
+
+    use super::*;
+
+    type CounterSim = Simulation<(), PaxosMessage<Request>, Paxos<Counter>, PaxosTimer>;
+
+    const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+    /// A cluster that elected a leader whose followers heard of it, and the
+    /// leader.
+    fn cluster(seed: u64) -> (CounterSim, String) {
+        let mut sim = CounterSim::new(3, seed, ()).unwrap();
+        let leader = sim.run_until_leader(Duration::from_secs(2)).unwrap();
+        sim.run_for(Duration::from_millis(200)).unwrap();
+        (sim, leader)
+    }
+
+    fn read(sim: &mut CounterSim, node_id: &str) -> i64 {
+        let reply = sim.call_payload(node_id, Request::Read, Duration::from_secs(2));
+        let reply = reply.unwrap().expect("no reply to read");
+        assert_eq!(reply["type"], "read_ok", "on {}: {}", node_id, reply);
+        reply["value"].as_i64().unwrap()
+    }
+
+    /// Sends one add of 1 to every node in turn, `count` in all, and returns
+    /// how many were acknowledged.
+    fn add(sim: &mut CounterSim, nodes: &[impl AsRef<str>], count: usize) -> i64 {
+        let requests = (0..count).map(|_| Request::Add { delta: 1 });
+        let replies = sim
+            .request_all(
+                nodes,
+                requests,
+                Duration::from_millis(100),
+                Duration::from_secs(2),
+            )
+            .unwrap();
+        let acked = replies
+            .iter()
+            .filter(|reply| reply.as_ref().is_some_and(|r| r["type"] == "add_ok"))
+            .count();
+        acked as i64
+    }
+
+    /// Every node reads the same value, one that counts every acknowledged
+    /// add and no more adds than were sent.
+    fn assert_converged(sim: &mut CounterSim, acked: i64, sent: i64) {
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        let value = read(sim, "n0");
+        assert!(
+            (acked..=sent).contains(&value),
+            "{} not in {}..={}",
+            value,
+            acked,
+            sent
+        );
+        for node_id in NODES {
+            assert_eq!(read(sim, node_id), value, "on {}", node_id);
+            assert_eq!(sim.node(node_id).unwrap().state().value, value);
+        }
+    }
+
+    #[test]
+    fn adds_through_any_node_are_counted_once() {
+        let (mut sim, _) = cluster(0);
+        let acked = add(&mut sim, &NODES, 9);
+        assert_eq!(acked, 9);
+        assert_converged(&mut sim, 9, 9);
+    }
+
+    #[test]
+    fn lost_messages_do_not_stall_the_log() {
+        for seed in 0..5 {
+            let (mut sim, _) = cluster(seed);
+            sim.set_faults("drop 0.2".parse().unwrap());
+            let acked = add(&mut sim, &NODES, 20);
+            sim.heal();
+            assert_converged(&mut sim, acked, 20);
+        }
+    }
+
+    #[test]
+    fn an_isolated_leader_is_replaced_and_catches_up() {
+        let (mut sim, old_leader) = cluster(1);
+        let majority = sim.others(&old_leader);
+        sim.isolate(&old_leader);
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_eq!(add(&mut sim, &majority, 6), 6);
+
+        sim.heal();
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_ne!(sim.leader().unwrap(), old_leader);
+        assert_converged(&mut sim, 6, 6);
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        /// The replies sent to the client so far.
+        fn client_replies(&self) -> Vec<Value> {
+            let output = self.0.lock().unwrap();
+            serde_json::Deserializer::from_slice(&output)
+                .into_iter::<Message<Value>>()
+                .map(Result::unwrap)
+                .filter(|message| message.dest == "c1")
+                .map(|message| message.body.payload)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn a_request_whose_slot_is_taken_is_proposed_again() {
+        let init = Init {
+            node_id: "n0".to_string(),
+            node_ids: NODES.map(str::to_string).to_vec(),
+        };
+        let output = Output::default();
+        let clock = Clock::starting_at(Instant::now());
+        let rng = StdRng::seed_from_u64(0);
+        let mut ctx = NodeContext::with_clock(init, Box::new(output.clone()), clock, rng);
+        let mut node = Paxos::<Counter>::new((), &mut ctx);
+        let mut msg_id = 0;
+        let mut message = |src: &str, payload: PaxosMessage<Request>| {
+            Message::new(src.to_string(), "n0".to_string(), payload, &mut msg_id)
+        };
+        let decide = |slot, request| {
+            PaxosMessage::Paxos(PaxosPayload::Decide {
+                decided: vec![Decided { slot, request }],
+            })
+        };
+
+        node.on_timer(PaxosTimer::Election, &mut ctx).unwrap();
+        let ballot = Ballot {
+            round: 1,
+            node: "n0".to_string(),
+        };
+        let promise = PaxosMessage::Paxos(PaxosPayload::Promise {
+            ballot,
+            accepted: Vec::new(),
+        });
+        node.handle(message("n1", promise), &mut ctx).unwrap();
+        assert_eq!(node.leader(), Some("n0"));
+
+        let add = Request::Add { delta: 1 };
+        node.handle(message("c1", PaxosMessage::Client(add.clone())), &mut ctx)
+            .unwrap();
+        // An earlier leader had filled slot 0 with a no-op.
+        node.handle(message("n1", decide(0, None)), &mut ctx)
+            .unwrap();
+        assert!(output.client_replies().is_empty());
+        node.handle(message("n1", decide(1, Some(add))), &mut ctx)
+            .unwrap();
+        let replies = output.client_replies();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["type"], "add_ok");
+        assert_eq!(node.state().value, 1);
+    }
+
+    #[test]
+    fn overflowing_adds_are_refused() {
+        let mut counter = Counter::default();
+        assert!(counter.apply(&Request::Add { delta: i64::MAX }).is_ok());
+        let error = counter.apply(&Request::Add { delta: 1 }).unwrap_err();
+        assert_eq!(error.code, ErrorCode::MalformedRequest);
+        assert_eq!(counter.value, i64::MAX);
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use dist_system::{
+    error::{Error, ErrorCode},
+    main_loop,
+    paxos::{Paxos, PaxosMessage, PaxosTimer, StateMachine},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum Request {
+    Read { key: Value },
+    Write { key: Value, value: Value },
+    Cas { key: Value, from: Value, to: Value },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
+enum Response {
+    ReadOk { value: Value },
+    WriteOk,
+    CasOk,
+}
+
+/// A linearizable key-value store, as served to the `lin-kv` workload.
+#[derive(Default)]
+struct Store {
+    values: HashMap<String, Value>,
+}
+
+impl StateMachine for Store {
+    type Request = Request;
+    type Response = Response;
+
+    fn apply(&mut self, request: &Request) -> Result<Response, Error> {
+        let missing = |key: &Value| {
+            Error::new(
+                ErrorCode::KeyDoesNotExist,
+                format!("key {} does not exist", key),
+            )
+        };
+        match request {
+            Request::Read { key } => match self.values.get(&key.to_string()) {
+                Some(value) => Ok(Response::ReadOk {
+                    value: value.clone(),
+                }),
+                None => Err(missing(key)),
+            },
+            Request::Write { key, value } => {
+                self.values.insert(key.to_string(), value.clone());
+                Ok(Response::WriteOk)
+            }
+            Request::Cas { key, from, to } => match self.values.get_mut(&key.to_string()) {
+                None => Err(missing(key)),
+                Some(value) if value != from => Err(Error::new(
+                    ErrorCode::PreconditionFailed,
+                    format!("expected {}, but had {}", from, value),
+                )),
+                Some(value) => {
+                    *value = to.clone();
+                    Ok(Response::CasOk)
+                }
+            },
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<(), PaxosMessage<Request>, Paxos<Store>, PaxosTimer>(())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use dist_system::sim::Simulation;
+    use serde_json::json;
+
+    use super::*;
+
+    type KvSim = Simulation<(), PaxosMessage<Request>, Paxos<Store>, PaxosTimer>;
+
+    /// A cluster that elected a leader whose followers heard of it, and the
+    /// leader.
+    fn cluster(seed: u64) -> (KvSim, String) {
+        let mut sim = KvSim::new(3, seed, ()).unwrap();
+        let leader = sim.run_until_leader(Duration::from_secs(2)).unwrap();
+        sim.run_for(Duration::from_millis(200)).unwrap();
+        (sim, leader)
+    }
+
+    fn call(sim: &mut KvSim, node_id: &str, request: Request) -> Option<Value> {
+        sim.call_payload(node_id, request, Duration::from_secs(2))
+            .unwrap()
+    }
+
+    fn read(sim: &mut KvSim, node_id: &str) -> Value {
+        let reply = call(sim, node_id, Request::Read { key: json!(1) });
+        let reply = reply.expect("no reply to read");
+        assert_eq!(reply["type"], "read_ok", "on {}: {}", node_id, reply);
+        reply["value"].clone()
+    }
+
+    /// Writes 0, 1, ... to one key through every node in turn, returning the
+    /// last value that was acknowledged.
+    fn write(sim: &mut KvSim, nodes: &[impl AsRef<str>], count: u64) -> Option<u64> {
+        let requests = (0..count).map(|value| Request::Write {
+            key: json!(1),
+            value: json!(value),
+        });
+        let replies = sim
+            .request_all(
+                nodes,
+                requests,
+                Duration::from_millis(100),
+                Duration::from_secs(2),
+            )
+            .unwrap();
+        replies
+            .iter()
+            .rposition(|reply| reply.as_ref().is_some_and(|r| r["type"] == "write_ok"))
+            .map(|value| value as u64)
+    }
+
+    /// Every node reads the same value and holds the same map.
+    fn assert_converged(sim: &mut KvSim) -> Value {
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        let value = read(sim, "n0");
+        let values = sim.node("n0").unwrap().state().values.clone();
+        for node_id in sim.node_ids().cloned().collect::<Vec<_>>() {
+            assert_eq!(read(sim, &node_id), value, "on {}", node_id);
+            assert_eq!(sim.node(&node_id).unwrap().state().values, values);
+        }
+        value
+    }
+
+    #[test]
+    fn requests_through_any_node_are_linearized() {
+        let (mut sim, _) = cluster(0);
+        assert_eq!(write(&mut sim, &["n0", "n1", "n2"], 6), Some(5));
+        assert_eq!(assert_converged(&mut sim), 5);
+
+        let cas = |from, to| Request::Cas {
+            key: json!(1),
+            from: json!(from),
+            to: json!(to),
+        };
+        assert_eq!(call(&mut sim, "n1", cas(5, 6)).unwrap()["type"], "cas_ok");
+        let reply = call(&mut sim, "n2", cas(5, 7)).unwrap();
+        assert_eq!(reply["code"], ErrorCode::PreconditionFailed.code());
+        let reply = call(&mut sim, "n0", Request::Read { key: json!(2) }).unwrap();
+        assert_eq!(reply["code"], ErrorCode::KeyDoesNotExist.code());
+        assert_eq!(assert_converged(&mut sim), 6);
+    }
+
+    #[test]
+    fn lost_messages_do_not_stall_the_log() {
+        for seed in 0..5 {
+            let (mut sim, _) = cluster(seed);
+            sim.set_faults("drop 0.2".parse().unwrap());
+            write(&mut sim, &["n0", "n1", "n2"], 20);
+            sim.heal();
+            assert_converged(&mut sim);
+        }
+    }
+
+    #[test]
+    fn an_isolated_leader_is_replaced_and_catches_up() {
+        let (mut sim, old_leader) = cluster(2);
+        let majority = sim.others(&old_leader);
+        sim.isolate(&old_leader);
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_eq!(write(&mut sim, &majority, 4), Some(3));
+
+        sim.heal();
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_ne!(sim.leader().unwrap(), old_leader);
+        assert_eq!(read(&mut sim, &old_leader), 3);
+        assert_eq!(assert_converged(&mut sim), 3);
+    }
+}
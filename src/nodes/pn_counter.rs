@@ -0,0 +1,89 @@
+use dist_system::{
+    context::NodeContext,
+    crdt::PNCounter,
+    gossip::{DeltaSync, GossipConfig},
+    main_loop, Message, Node,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum Payload {
+    Read,
+    ReadOk { value: i64 },
+    Add { delta: i64 },
+    AddOk,
+    Propogate { counter: PNCounter, upto: u64 },
+    PropogateOk { upto: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Timer {
+    Gossip,
+}
+
+struct PNCounterNode {
+    counter: DeltaSync<PNCounter>,
+}
+
+impl PNCounterNode {
+    fn propogate(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        for (node, upto, counter) in self.counter.batches(ctx.rng()) {
+            ctx.send(node, Payload::Propogate { counter, upto })?;
+        }
+        Ok(())
+    }
+}
+
+impl Node<Payload, GossipConfig, Timer> for PNCounterNode {
+    fn new(config: GossipConfig, ctx: &mut NodeContext<Timer>) -> Self {
+        ctx.timers.every(Timer::Gossip, config.interval);
+        PNCounterNode {
+            counter: DeltaSync::new(config, ctx.peers().cloned().collect()),
+        }
+    }
+
+    fn handle(
+        &mut self,
+        message: Message<Payload>,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
+        match &message.body.payload {
+            Payload::Add { delta } => {
+                self.counter
+                    .mutate(|counter| counter.add(&ctx.node_id, *delta));
+                ctx.reply(&message, Payload::AddOk)?;
+            }
+            Payload::AddOk => {}
+            Payload::Read => {
+                ctx.reply(
+                    &message,
+                    Payload::ReadOk {
+                        value: self.counter.state().value(),
+                    },
+                )?;
+            }
+            Payload::ReadOk { value: _ } => {}
+            Payload::Propogate { counter, upto } => {
+                self.counter.receive(counter.clone());
+                ctx.reply(&message, Payload::PropogateOk { upto: *upto })?;
+            }
+            Payload::PropogateOk { upto } => {
+                self.counter.ack(&message.src, *upto);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_timer(&mut self, timer: Timer, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        match timer {
+            Timer::Gossip => self.propogate(ctx),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<GossipConfig, Payload, PNCounterNode, Timer>(GossipConfig::from_env()?)?;
+    Ok(())
+}
@@ -0,0 +1,819 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use dist_system::{
+    context::NodeContext,
+    error::{Error, ErrorCode},
+    info, main_loop,
+    rpc::retry::RetryPolicy,
+    sim::Elected,
+    Message, Node,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A follower that has not heard from a leader for a random time in this
+/// range starts an election.
+const ELECTION_TIMEOUT_MIN: Duration = Duration::from_millis(300);
+const ELECTION_TIMEOUT_MAX: Duration = Duration::from_millis(600);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+/// How long after a majority acknowledged an `append_entries` the leader may
+/// still serve reads from its own state. It stays below
+/// `ELECTION_TIMEOUT_MIN`, during which those followers refuse to vote.
+const LEASE: Duration = Duration::from_millis(250);
+/// The most entries sent in a single `append_entries`.
+const MAX_BATCH: usize = 128;
+/// How long a follower waits for the leader to answer a forwarded request.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum Payload {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+    },
+    CasOk,
+    RequestVote {
+        term: u64,
+        last_log_index: usize,
+        last_log_term: u64,
+    },
+    RequestVoteResult {
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_log_index: usize,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+        leader_commit: usize,
+    },
+    AppendEntriesResult {
+        term: u64,
+        success: bool,
+        /// The last index known to match the leader on success, a guess to
+        /// retry from otherwise.
+        match_index: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "op")]
+enum Op {
+    /// Appended by every new leader so entries of earlier terms get
+    /// committed.
+    Noop,
+    Read {
+        key: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Entry {
+    term: u64,
+    op: Op,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Timer {
+    Election,
+    Heartbeat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// The key-value map the log is applied to.
+#[derive(Default)]
+struct Store {
+    values: HashMap<String, Value>,
+}
+
+impl Store {
+    fn apply(&mut self, op: &Op) -> Result<Payload, Error> {
+        match op {
+            Op::Noop => Ok(Payload::WriteOk),
+            Op::Read { key } => self.read(key),
+            Op::Write { key, value } => {
+                self.values.insert(key.to_string(), value.clone());
+                Ok(Payload::WriteOk)
+            }
+            Op::Cas { key, from, to } => match self.values.get_mut(&key.to_string()) {
+                None => Err(Error::new(
+                    ErrorCode::KeyDoesNotExist,
+                    format!("key {} does not exist", key),
+                )),
+                Some(value) if value != from => Err(Error::new(
+                    ErrorCode::PreconditionFailed,
+                    format!("expected {}, but had {}", from, value),
+                )),
+                Some(value) => {
+                    *value = to.clone();
+                    Ok(Payload::CasOk)
+                }
+            },
+        }
+    }
+
+    fn read(&self, key: &Value) -> Result<Payload, Error> {
+        match self.values.get(&key.to_string()) {
+            Some(value) => Ok(Payload::ReadOk {
+                value: value.clone(),
+            }),
+            None => Err(Error::new(
+                ErrorCode::KeyDoesNotExist,
+                format!("key {} does not exist", key),
+            )),
+        }
+    }
+}
+
+/// A linearizable key-value store replicated with Raft.
+///
+/// Clients may talk to any node: followers forward their requests to the
+/// leader they know of. Writes and compare-and-sets are answered once
+/// their log entry is applied. Reads are answered straight from the leader's
+/// state while it holds a lease, and go through the log otherwise.
+///
+/// A node that heard from a leader within `ELECTION_TIMEOUT_MIN` ignores
+/// vote requests, which is what makes the lease safe. State is kept in
+/// memory only, so a node must not restart.
+struct RaftNode {
+    role: Role,
+    current_term: u64,
+    voted_for: Option<String>,
+    leader_id: Option<String>,
+    /// `log[0]` is a sentinel, real entries start at index 1.
+    log: Vec<Entry>,
+    commit_index: usize,
+    last_applied: usize,
+    store: Store,
+    /// When the last `append_entries` of the current leader arrived.
+    last_heartbeat: Option<Instant>,
+    votes: HashSet<String>,
+    next_index: HashMap<String, usize>,
+    match_index: HashMap<String, usize>,
+    /// When every `append_entries` still waiting for a result was sent, by
+    /// `msg_id`.
+    sent_at: HashMap<usize, Instant>,
+    /// The send time of the latest `append_entries` every follower answered.
+    acked_at: HashMap<String, Instant>,
+    /// Client requests waiting for the entry at an index to be applied.
+    waiting: BTreeMap<usize, Message<Payload>>,
+}
+
+impl RaftNode {
+    fn last_index(&self) -> usize {
+        self.log.len() - 1
+    }
+
+    fn last_term(&self) -> u64 {
+        self.log[self.last_index()].term
+    }
+
+    fn majority(ctx: &NodeContext<Timer>) -> usize {
+        ctx.node_ids.len() / 2 + 1
+    }
+
+    fn reset_election_timer(&self, ctx: &mut NodeContext<Timer>) {
+        let timeout = ctx
+            .rng()
+            .gen_range(ELECTION_TIMEOUT_MIN..ELECTION_TIMEOUT_MAX);
+        ctx.timers.once(Timer::Election, timeout);
+    }
+
+    /// Moves to `term` as a follower if it is newer than ours.
+    fn observe_term(&mut self, term: u64, ctx: &mut NodeContext<Timer>) {
+        if term > self.current_term {
+            self.current_term = term;
+            self.voted_for = None;
+            self.step_down(ctx);
+        }
+    }
+
+    fn step_down(&mut self, ctx: &mut NodeContext<Timer>) {
+        if self.role == Role::Leader {
+            info!("stepping down in term {}", self.current_term);
+            ctx.timers.cancel(&Timer::Heartbeat);
+            self.sent_at.clear();
+            self.acked_at.clear();
+            // Their entries may still be committed by the next leader, so the
+            // outcome is unknown.
+            for request in std::mem::take(&mut self.waiting).into_values() {
+                let _ = ctx.reply_error(&request, ErrorCode::Timeout, "leadership lost");
+            }
+        }
+        self.role = Role::Follower;
+        self.leader_id = None;
+        self.reset_election_timer(ctx);
+    }
+
+    fn start_election(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        self.role = Role::Candidate;
+        self.current_term += 1;
+        self.voted_for = Some(ctx.node_id.clone());
+        self.leader_id = None;
+        self.votes = HashSet::from([ctx.node_id.clone()]);
+        self.reset_election_timer(ctx);
+        info!("starting election for term {}", self.current_term);
+
+        for peer in ctx.peers().cloned().collect::<Vec<_>>() {
+            ctx.send(
+                peer,
+                Payload::RequestVote {
+                    term: self.current_term,
+                    last_log_index: self.last_index(),
+                    last_log_term: self.last_term(),
+                },
+            )?;
+        }
+        self.count_votes(ctx)
+    }
+
+    fn count_votes(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        if self.role == Role::Candidate && self.votes.len() >= Self::majority(ctx) {
+            self.become_leader(ctx)?;
+        }
+        Ok(())
+    }
+
+    fn become_leader(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        info!("became leader for term {}", self.current_term);
+        self.role = Role::Leader;
+        self.leader_id = Some(ctx.node_id.clone());
+        self.log.push(Entry {
+            term: self.current_term,
+            op: Op::Noop,
+        });
+        let peers: Vec<String> = ctx.peers().cloned().collect();
+        self.next_index = peers
+            .iter()
+            .map(|p| (p.clone(), self.last_index()))
+            .collect();
+        self.match_index = peers.iter().map(|p| (p.clone(), 0)).collect();
+        ctx.timers.cancel(&Timer::Election);
+        ctx.timers.every(Timer::Heartbeat, HEARTBEAT_INTERVAL);
+        self.advance_commit(ctx)?;
+        self.replicate(ctx)
+    }
+
+    /// Sends every follower the entries it is missing, or an empty heartbeat.
+    fn replicate(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        let now = ctx.now();
+        self.sent_at
+            .retain(|_, sent| now.duration_since(*sent) < ELECTION_TIMEOUT_MAX);
+        for peer in ctx.peers().cloned().collect::<Vec<_>>() {
+            let next = self.next_index[&peer];
+            let end = self.log.len().min(next + MAX_BATCH);
+            let message = ctx.message(
+                peer,
+                Payload::AppendEntries {
+                    term: self.current_term,
+                    prev_log_index: next - 1,
+                    prev_log_term: self.log[next - 1].term,
+                    entries: self.log[next..end].to_vec(),
+                    leader_commit: self.commit_index,
+                },
+            );
+            if let Some(msg_id) = message.body.msg_id {
+                self.sent_at.insert(msg_id, now);
+            }
+            message.send(ctx.out())?;
+        }
+        Ok(())
+    }
+
+    /// Whether a majority confirmed our leadership recently enough that no
+    /// other leader can have been elected since.
+    fn has_lease(&self, ctx: &NodeContext<Timer>) -> bool {
+        let now = ctx.now();
+        let mut acked: Vec<Instant> = self.acked_at.values().copied().collect();
+        acked.push(now);
+        acked.sort_unstable_by(|a, b| b.cmp(a));
+        acked
+            .get(Self::majority(ctx) - 1)
+            .is_some_and(|since| now < *since + LEASE)
+    }
+
+    /// Commits the highest index a majority has, as long as it is from the
+    /// current term.
+    fn advance_commit(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        let mut matched: Vec<usize> = self.match_index.values().copied().collect();
+        matched.push(self.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let index = matched[Self::majority(ctx) - 1];
+        if index > self.commit_index && self.log[index].term == self.current_term {
+            self.commit_index = index;
+            self.apply(ctx)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let result = self.store.apply(&self.log[self.last_applied].op);
+            if let Some(request) = self.waiting.remove(&self.last_applied) {
+                match result {
+                    Ok(payload) => ctx.reply(&request, payload)?,
+                    Err(error) => ctx.reply(&request, error)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_client_request(
+        &mut self,
+        request: Message<Payload>,
+        op: Op,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
+        if self.role == Role::Leader {
+            // Nothing of an earlier term is known to be applied before the
+            // leader committed an entry of its own.
+            let caught_up = self.log[self.commit_index].term == self.current_term;
+            if let Op::Read { key } = &op {
+                if caught_up && self.has_lease(ctx) {
+                    return match self.store.read(key) {
+                        Ok(payload) => ctx.reply(&request, payload),
+                        Err(error) => ctx.reply(&request, error),
+                    };
+                }
+            }
+            self.log.push(Entry {
+                term: self.current_term,
+                op,
+            });
+            self.waiting.insert(self.last_index(), request);
+            if ctx.node_ids.len() == 1 {
+                return self.advance_commit(ctx);
+            }
+            return self.replicate(ctx);
+        }
+
+        // A request forwarded by a peer that thinks we lead is not passed on
+        // again, so it cannot go around in circles.
+        let leader = self
+            .leader_id
+            .clone()
+            .filter(|_| !ctx.node_ids.contains(&request.src));
+        let Some(leader) = leader else {
+            return ctx.reply_error(
+                &request,
+                ErrorCode::TemporarilyUnavailable,
+                "no leader known",
+            );
+        };
+        let payload = request.body.payload.clone();
+        ctx.call_with(
+            leader,
+            payload,
+            RetryPolicy::once(FORWARD_TIMEOUT),
+            move |ctx, reply: anyhow::Result<Message<Payload>>| match reply {
+                Ok(reply) => ctx.reply(&request, reply.body.payload),
+                Err(error) => ctx.reply(&request, Error::from(error)),
+            },
+        )
+    }
+
+    fn on_request_vote(
+        &mut self,
+        request: &Message<Payload>,
+        term: u64,
+        last_log_index: usize,
+        last_log_term: u64,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
+        let heard_from_leader = self.role == Role::Leader
+            || self
+                .last_heartbeat
+                .is_some_and(|at| ctx.now().duration_since(at) < ELECTION_TIMEOUT_MIN);
+        if heard_from_leader {
+            return ctx.reply(
+                request,
+                Payload::RequestVoteResult {
+                    term: self.current_term,
+                    vote_granted: false,
+                },
+            );
+        }
+
+        self.observe_term(term, ctx);
+        let up_to_date = (last_log_term, last_log_index) >= (self.last_term(), self.last_index());
+        let vote_granted = term == self.current_term
+            && up_to_date
+            && self
+                .voted_for
+                .as_ref()
+                .is_none_or(|voted| *voted == request.src);
+        if vote_granted {
+            self.voted_for = Some(request.src.clone());
+            self.reset_election_timer(ctx);
+        }
+        ctx.reply(
+            request,
+            Payload::RequestVoteResult {
+                term: self.current_term,
+                vote_granted,
+            },
+        )
+    }
+
+    fn on_append_entries(
+        &mut self,
+        request: &Message<Payload>,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
+        let Payload::AppendEntries {
+            term,
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit,
+        } = &request.body.payload
+        else {
+            return Ok(());
+        };
+        let (term, prev_log_index, prev_log_term, leader_commit) =
+            (*term, *prev_log_index, *prev_log_term, *leader_commit);
+        if term < self.current_term {
+            return ctx.reply(
+                request,
+                Payload::AppendEntriesResult {
+                    term: self.current_term,
+                    success: false,
+                    match_index: 0,
+                },
+            );
+        }
+        self.observe_term(term, ctx);
+        if self.role != Role::Follower {
+            self.step_down(ctx);
+        }
+        self.leader_id = Some(request.src.clone());
+        self.last_heartbeat = Some(ctx.now());
+        self.reset_election_timer(ctx);
+
+        if prev_log_index > self.last_index() || self.log[prev_log_index].term != prev_log_term {
+            return ctx.reply(
+                request,
+                Payload::AppendEntriesResult {
+                    term: self.current_term,
+                    success: false,
+                    match_index: self.last_index().min(prev_log_index.saturating_sub(1)),
+                },
+            );
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            let index = prev_log_index + 1 + i;
+            match self.log.get(index) {
+                Some(existing) if existing.term == entry.term => {}
+                Some(_) => {
+                    self.log.truncate(index);
+                    self.log.push(entry.clone());
+                }
+                None => self.log.push(entry.clone()),
+            }
+        }
+        let match_index = prev_log_index + entries.len();
+        if leader_commit > self.commit_index {
+            // A stale or reordered request may match less than we already
+            // committed, which must never be taken back.
+            self.commit_index = self.commit_index.max(leader_commit.min(match_index));
+            self.apply(ctx)?;
+        }
+        ctx.reply(
+            request,
+            Payload::AppendEntriesResult {
+                term: self.current_term,
+                success: true,
+                match_index,
+            },
+        )
+    }
+
+    fn on_append_entries_result(
+        &mut self,
+        result: &Message<Payload>,
+        term: u64,
+        success: bool,
+        match_index: usize,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
+        self.observe_term(term, ctx);
+        let sent_at = result
+            .body
+            .in_reply_to
+            .and_then(|msg_id| self.sent_at.remove(&msg_id));
+        if self.role != Role::Leader || term != self.current_term {
+            return Ok(());
+        }
+        let peer = &result.src;
+        if let Some(sent_at) = sent_at {
+            let acked = self.acked_at.entry(peer.clone()).or_insert(sent_at);
+            *acked = (*acked).max(sent_at);
+        }
+        if success {
+            let matched = self.match_index.entry(peer.clone()).or_default();
+            *matched = (*matched).max(match_index);
+            self.next_index.insert(peer.clone(), *matched + 1);
+            self.advance_commit(ctx)
+        } else {
+            let next = self.next_index.entry(peer.clone()).or_insert(1);
+            *next = (match_index + 1).min(*next - 1).max(1);
+            Ok(())
+        }
+    }
+}
+
+impl Elected for RaftNode {
+    fn leading(&self) -> Option<u64> {
+        (self.role == Role::Leader).then_some(self.current_term)
+    }
+}
+
+impl Node<Payload, (), Timer> for RaftNode {
+    fn new(_state: (), ctx: &mut NodeContext<Timer>) -> Self {
+        let node = RaftNode {
+            role: Role::Follower,
+            current_term: 0,
+            voted_for: None,
+            leader_id: None,
+            log: vec![Entry {
+                term: 0,
+                op: Op::Noop,
+            }],
+            commit_index: 0,
+            last_applied: 0,
+            store: Store::default(),
+            last_heartbeat: None,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            sent_at: HashMap::new(),
+            acked_at: HashMap::new(),
+            waiting: BTreeMap::new(),
+        };
+        node.reset_election_timer(ctx);
+        node
+    }
+
+    fn handle(
+        &mut self,
+        message: Message<Payload>,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
+        match message.body.payload.clone() {
+            Payload::Read { key } => self.on_client_request(message, Op::Read { key }, ctx)?,
+            Payload::Write { key, value } => {
+                self.on_client_request(message, Op::Write { key, value }, ctx)?
+            }
+            Payload::Cas { key, from, to } => {
+                self.on_client_request(message, Op::Cas { key, from, to }, ctx)?
+            }
+            Payload::ReadOk { .. } | Payload::WriteOk | Payload::CasOk => {}
+            Payload::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => self.on_request_vote(&message, term, last_log_index, last_log_term, ctx)?,
+            Payload::RequestVoteResult { term, vote_granted } => {
+                self.observe_term(term, ctx);
+                if vote_granted && term == self.current_term {
+                    self.votes.insert(message.src.clone());
+                    self.count_votes(ctx)?;
+                }
+            }
+            Payload::AppendEntries { .. } => self.on_append_entries(&message, ctx)?,
+            Payload::AppendEntriesResult {
+                term,
+                success,
+                match_index,
+            } => self.on_append_entries_result(&message, term, success, match_index, ctx)?,
+        }
+        Ok(())
+    }
+
+    fn on_timer(&mut self, timer: Timer, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        match timer {
+            Timer::Election if self.role != Role::Leader => self.start_election(ctx),
+            Timer::Election => Ok(()),
+            Timer::Heartbeat => self.replicate(ctx),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<(), Payload, RaftNode, Timer>(())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use dist_system::{clock::Clock, sim::Simulation, Init};
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    use super::*;
+
+    type RaftSim = Simulation<(), Payload, RaftNode, Timer>;
+
+    const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+    /// A cluster that elected a leader whose followers heard of it, and the
+    /// leader.
+    fn cluster(seed: u64) -> (RaftSim, String) {
+        let mut sim = RaftSim::new(3, seed, ()).unwrap();
+        let leader = sim.run_until_leader(Duration::from_secs(2)).unwrap();
+        sim.run_for(HEARTBEAT_INTERVAL * 2).unwrap();
+        (sim, leader)
+    }
+
+    fn call(sim: &mut RaftSim, node_id: &str, payload: Payload) -> Option<Value> {
+        sim.call_payload(node_id, payload, Duration::from_secs(2))
+            .unwrap()
+    }
+
+    fn write(sim: &mut RaftSim, node_id: &str, value: u64) {
+        let payload = Payload::Write {
+            key: json!(1),
+            value: json!(value),
+        };
+        let reply = call(sim, node_id, payload).expect("no reply to write");
+        assert_eq!(reply["type"], "write_ok", "{}", reply);
+    }
+
+    fn read(sim: &mut RaftSim, node_id: &str) -> Option<Value> {
+        call(sim, node_id, Payload::Read { key: json!(1) })
+    }
+
+    #[test]
+    fn one_leader_is_elected_and_followed() {
+        for seed in 0..5 {
+            let (sim, leader) = cluster(seed);
+            let term = sim.node(&leader).unwrap().current_term;
+            for node_id in NODES {
+                let node = sim.node(node_id).unwrap();
+                assert_eq!(node.current_term, term, "seed {} on {}", seed, node_id);
+                assert_eq!(node.leader_id.as_deref(), Some(leader.as_str()));
+                if node_id != leader {
+                    assert_eq!(node.role, Role::Follower, "seed {} on {}", seed, node_id);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn followers_forward_requests_to_the_leader() {
+        let (mut sim, leader) = cluster(1);
+        let [a, b] = &sim.others(&leader)[..] else {
+            unreachable!()
+        };
+        let (a, b) = (a.clone(), b.clone());
+        write(&mut sim, &a, 3);
+        assert_eq!(read(&mut sim, &b).unwrap()["value"], 3);
+
+        let cas = |from, to| Payload::Cas {
+            key: json!(1),
+            from: json!(from),
+            to: json!(to),
+        };
+        assert_eq!(call(&mut sim, &b, cas(3, 4)).unwrap()["type"], "cas_ok");
+        let reply = call(&mut sim, &a, cas(3, 5)).unwrap();
+        assert_eq!(reply["code"], ErrorCode::PreconditionFailed.code());
+        assert_eq!(read(&mut sim, &leader).unwrap()["value"], 4);
+    }
+
+    #[test]
+    fn leaders_serve_reads_from_their_lease() {
+        let (mut sim, leader) = cluster(2);
+        write(&mut sim, &leader, 7);
+        sim.run_for(HEARTBEAT_INTERVAL * 2).unwrap();
+
+        let logged = sim.node(&leader).unwrap().log.len();
+        assert_eq!(read(&mut sim, &leader).unwrap()["value"], 7);
+        assert_eq!(sim.node(&leader).unwrap().log.len(), logged);
+
+        // Cut off from the majority, the lease runs out and reads have to go
+        // through a log that no longer commits.
+        sim.isolate(&leader);
+        sim.run_for(ELECTION_TIMEOUT_MAX * 2).unwrap();
+        let new_leader = sim.leader_among(sim.others(&leader)).unwrap();
+        write(&mut sim, &new_leader, 8);
+        let reply = read(&mut sim, &leader);
+        assert!(
+            reply.as_ref().is_none_or(|r| r["type"] != "read_ok"),
+            "stale read {:?}",
+            reply
+        );
+    }
+
+    #[test]
+    fn a_healed_partition_catches_up() {
+        let (mut sim, old_leader) = cluster(3);
+        write(&mut sim, &old_leader, 1);
+
+        sim.isolate(&old_leader);
+        sim.run_for(ELECTION_TIMEOUT_MAX * 2).unwrap();
+        let new_leader = sim.leader_among(sim.others(&old_leader)).unwrap();
+        write(&mut sim, &new_leader, 2);
+
+        sim.heal();
+        sim.run_for(Duration::from_secs(2)).unwrap();
+        let leader = sim.leader().unwrap();
+        assert_ne!(leader, old_leader);
+        let commit = sim.node(&leader).unwrap().commit_index;
+        for node_id in NODES {
+            let node = sim.node(node_id).unwrap();
+            assert_eq!(
+                node.role == Role::Leader,
+                node_id == leader,
+                "on {}",
+                node_id
+            );
+            assert_eq!(node.last_applied, commit, "on {}", node_id);
+            assert_eq!(node.store.values[&json!(1).to_string()], json!(2));
+        }
+        for node_id in NODES {
+            assert_eq!(
+                read(&mut sim, node_id).unwrap()["value"],
+                2,
+                "on {}",
+                node_id
+            );
+        }
+    }
+
+    #[test]
+    fn stale_append_entries_never_lower_the_commit_index() {
+        let init = Init {
+            node_id: "n1".to_string(),
+            node_ids: NODES.map(str::to_string).to_vec(),
+        };
+        let clock = Clock::starting_at(Instant::now());
+        let mut ctx =
+            NodeContext::with_clock(init, Box::new(io::sink()), clock, StdRng::seed_from_u64(0));
+        let mut node = RaftNode::new((), &mut ctx);
+        let entry = Entry {
+            term: 1,
+            op: Op::Noop,
+        };
+        let mut msg_id = 0;
+        let mut append = |entries: usize, leader_commit: usize| {
+            Message::new(
+                "n0".to_string(),
+                "n1".to_string(),
+                Payload::AppendEntries {
+                    term: 1,
+                    prev_log_index: 0,
+                    prev_log_term: 0,
+                    entries: vec![entry.clone(); entries],
+                    leader_commit,
+                },
+                &mut msg_id,
+            )
+        };
+        let (current, stale) = (append(2, 2), append(1, 3));
+        node.handle(current, &mut ctx).unwrap();
+        assert_eq!(node.commit_index, 2);
+        node.handle(stale, &mut ctx).unwrap();
+        assert_eq!(node.commit_index, 2);
+        assert_eq!(node.last_applied, 2);
+    }
+}
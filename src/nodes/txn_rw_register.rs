@@ -0,0 +1,148 @@
+use dist_system::{
+    context::NodeContext,
+    gossip::{DeltaGossip, GossipConfig},
+    main_loop,
+    txn::{Committed, Isolation, MicroOp, TxnStore, Version},
+    Message, Node,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum Payload {
+    Txn { txn: Vec<MicroOp> },
+    TxnOk { txn: Vec<MicroOp> },
+    Propogate { txns: Vec<Committed> },
+    PropogateOk { versions: Vec<Version> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Timer {
+    Gossip,
+}
+
+/// Runs every transaction on the node it arrives at and answers right away,
+/// so it stays available under partitions. Committed writes reach the other
+/// nodes by gossip.
+struct TxnNode {
+    store: TxnStore,
+    txns: DeltaGossip<Version, Committed>,
+}
+
+impl TxnNode {
+    fn propogate(&mut self, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        for (node, txns) in self.txns.batches(ctx.rng()) {
+            let txns = txns.into_iter().map(|(_, committed)| committed).collect();
+            ctx.send(node, Payload::Propogate { txns })?;
+        }
+        Ok(())
+    }
+}
+
+impl Node<Payload, (Isolation, GossipConfig), Timer> for TxnNode {
+    fn new((isolation, config): (Isolation, GossipConfig), ctx: &mut NodeContext<Timer>) -> Self {
+        ctx.timers.every(Timer::Gossip, config.interval);
+        TxnNode {
+            store: TxnStore::new(isolation, ctx.node_id.clone()),
+            txns: DeltaGossip::new(config, ctx.peers().cloned().collect()),
+        }
+    }
+
+    fn handle(
+        &mut self,
+        message: Message<Payload>,
+        ctx: &mut NodeContext<Timer>,
+    ) -> anyhow::Result<()> {
+        match &message.body.payload {
+            Payload::Txn { txn } => {
+                let executed = self.store.execute(txn);
+                if let Some(committed) = executed.committed {
+                    self.txns.insert(committed.version.clone(), committed, []);
+                }
+                match executed.result {
+                    Ok(txn) => ctx.reply(&message, Payload::TxnOk { txn })?,
+                    Err(error) => ctx.reply(&message, error)?,
+                }
+            }
+            Payload::TxnOk { txn: _ } => {}
+            Payload::Propogate { txns } => {
+                for committed in txns {
+                    let version = committed.version.clone();
+                    if self
+                        .txns
+                        .insert(version, committed.clone(), [message.src.as_str()])
+                    {
+                        self.store.merge(committed);
+                    }
+                }
+                let versions = txns.iter().map(|c| c.version.clone()).collect();
+                ctx.reply(&message, Payload::PropogateOk { versions })?;
+            }
+            Payload::PropogateOk { versions } => {
+                self.txns.ack(&message.src, versions);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_timer(&mut self, timer: Timer, ctx: &mut NodeContext<Timer>) -> anyhow::Result<()> {
+        match timer {
+            Timer::Gossip => self.propogate(ctx),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<(Isolation, GossipConfig), Payload, TxnNode, Timer>((
+        Isolation::from_env()?,
+        GossipConfig::from_env()?,
+    ))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use dist_system::{sim::Simulation, txn::Function};
+
+    use super::*;
+
+    type TxnSim = Simulation<(Isolation, GossipConfig), Payload, TxnNode, Timer>;
+
+    fn simulate(seed: u64) -> TxnSim {
+        let state = (Isolation::default(), GossipConfig::default());
+        let mut sim = TxnSim::new(3, seed, state).unwrap();
+        sim.set_faults("drop 0.3".parse().unwrap());
+        for i in 0..12 {
+            let txn = vec![
+                MicroOp(Function::Write, i % 4, Some(i)),
+                MicroOp(Function::Write, i % 3, Some(i)),
+            ];
+            let node_id = format!("n{}", i % 3);
+            sim.request(&node_id, Payload::Txn { txn }).unwrap();
+        }
+        sim.run_for(Duration::from_secs(2)).unwrap();
+        sim
+    }
+
+    #[test]
+    fn a_seed_replays_the_same_gossip() {
+        assert_eq!(simulate(3).journal(), simulate(3).journal());
+    }
+
+    #[test]
+    fn writes_every_node_has_are_dropped_from_gossip() {
+        let mut sim = simulate(5);
+        sim.heal();
+        sim.run_for(Duration::from_secs(2)).unwrap();
+        for node_id in ["n0", "n1", "n2"] {
+            assert!(
+                sim.node(node_id).unwrap().txns.items().is_empty(),
+                "on {}",
+                node_id
+            );
+        }
+    }
+}
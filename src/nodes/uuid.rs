@@ -1,6 +1,4 @@
-use std::io::Write;
-
-use dist_system::{main_loop, Init, Message, Node};
+use dist_system::{context::NodeContext, main_loop, Message, Node};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -11,30 +9,21 @@ enum Payload {
     GenerateOk { id: String },
 }
 
-struct UUIDNode {
-    id: usize,
-    node_name: String,
-}
+struct UUIDNode;
 
 impl Node<Payload, ()> for UUIDNode {
-    fn new(_state: (), init: Init) -> Self {
-        UUIDNode {
-            id: 2,
-            node_name: init.node_id,
-        }
+    fn new(_state: (), _ctx: &mut NodeContext) -> Self {
+        UUIDNode
     }
 
-    fn handle(&mut self, message: Message<Payload>, out: &mut impl Write) -> anyhow::Result<()> {
+    fn handle(&mut self, message: Message<Payload>, ctx: &mut NodeContext) -> anyhow::Result<()> {
         match &message.body.payload {
             Payload::Generate => {
-                message
-                    .reply(
-                        Payload::GenerateOk {
-                            id: format!("{}-{}", self.node_name, self.id),
-                        },
-                        &mut self.id,
-                    )
-                    .send(out)?;
+                // Message ids never repeat on a node, so one paired with the
+                // node id is unique across the cluster.
+                let seq = ctx.next_msg_id();
+                let id = format!("{}-{}", ctx.node_id, seq);
+                ctx.reply(&message, Payload::GenerateOk { id })?;
             }
             Payload::GenerateOk { id: _ } => {}
         }
@@ -43,6 +32,6 @@ impl Node<Payload, ()> for UUIDNode {
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), Payload, UUIDNode>(())?;
+    main_loop::<(), Payload, UUIDNode, ()>(())?;
     Ok(())
 }
@@ -0,0 +1,584 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    context::NodeContext,
+    error::{Error, ErrorCode},
+    info,
+    rpc::retry::RetryPolicy,
+    sim::Elected,
+    Message, Node,
+};
+
+/// A node that has not heard from a leader for a random time in this range
+/// tries to become one.
+const ELECTION_TIMEOUT_MIN: Duration = Duration::from_millis(300);
+const ELECTION_TIMEOUT_MAX: Duration = Duration::from_millis(600);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+/// The most decided slots sent to a lagging node at once, and the most
+/// undecided ones proposed again per heartbeat.
+const MAX_CATCH_UP: usize = 128;
+/// How long a node waits for the leader to answer a forwarded request.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The deterministic state every node applies the decided requests to.
+pub trait StateMachine: Default {
+    /// A client request, as it arrives on the wire.
+    type Request: Serialize + DeserializeOwned + Debug + Clone + PartialEq + Send + 'static;
+    /// What a request is answered with.
+    type Response: Serialize + DeserializeOwned + Debug + 'static;
+
+    fn apply(&mut self, request: &Self::Request) -> Result<Self::Response, Error>;
+}
+
+/// A proposal number. Ballots of different nodes never compare equal, so at
+/// most one node leads with a given ballot.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ballot {
+    pub round: u64,
+    pub node: String,
+}
+
+/// A value an acceptor accepted for a slot. A `None` request is a no-op
+/// that fills a gap left by an earlier leader.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Accepted<R> {
+    pub slot: usize,
+    pub ballot: Ballot,
+    pub request: Option<R>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Decided<R> {
+    pub slot: usize,
+    pub request: Option<R>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum PaxosPayload<R> {
+    /// Phase 1a, for every slot from `from_slot` on.
+    Prepare {
+        ballot: Ballot,
+        from_slot: usize,
+    },
+    /// Phase 1b, with everything accepted from the requested slot on.
+    Promise {
+        ballot: Ballot,
+        accepted: Vec<Accepted<R>>,
+    },
+    /// Phase 2a.
+    Accept {
+        ballot: Ballot,
+        slot: usize,
+        request: Option<R>,
+    },
+    /// Phase 2b.
+    Accepted {
+        ballot: Ballot,
+        slot: usize,
+    },
+    /// Refuses a `prepare` or `accept` because of a higher promise.
+    Nack {
+        ballot: Ballot,
+    },
+    Decide {
+        decided: Vec<Decided<R>>,
+    },
+    /// Sent by the leader every `HEARTBEAT_INTERVAL`, with how many slots it
+    /// has applied.
+    Heartbeat {
+        ballot: Ballot,
+        applied: usize,
+    },
+    /// Tells the leader how many slots a node has applied when it is behind.
+    HeartbeatOk {
+        applied: usize,
+    },
+}
+
+/// Everything a `Paxos` node reads: client requests of the state machine
+/// and the protocol's own messages.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PaxosMessage<R> {
+    Client(R),
+    Paxos(PaxosPayload<R>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaxosTimer {
+    Election,
+    Heartbeat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    /// Waiting for a majority of promises for `ballot`.
+    Candidate,
+    Leader,
+}
+
+/// A replicated state machine kept consistent with Multi-Paxos.
+///
+/// Every node is an acceptor and a learner. The node whose ballot a
+/// majority promised leads: it runs phase 1 once for every slot and then
+/// only phase 2 per request until somebody prepares a higher ballot.
+/// Heartbeats keep followers from campaigning, and tell lagging ones to
+/// catch up on decided slots.
+///
+/// Clients may talk to any node, followers forward requests to the leader
+/// they know of. Every request, reads included, goes through the log and is
+/// answered once applied. State is kept in memory only.
+pub struct Paxos<SM: StateMachine> {
+    state: SM,
+    role: Role,
+    leader_id: Option<String>,
+    last_heartbeat: Option<Instant>,
+    /// The ballot this node leads or campaigns with.
+    ballot: Ballot,
+    // Acceptor.
+    promised: Ballot,
+    accepted: BTreeMap<usize, Accepted<SM::Request>>,
+    // Learner.
+    decided: BTreeMap<usize, Option<SM::Request>>,
+    /// Every slot below it is applied.
+    applied: usize,
+    // Leader.
+    promises: HashMap<String, Vec<Accepted<SM::Request>>>,
+    next_slot: usize,
+    proposals: HashMap<usize, Option<SM::Request>>,
+    votes: HashMap<usize, HashSet<String>>,
+    /// Clients waiting for the slot their request was proposed in.
+    waiting: BTreeMap<usize, (ClientMessage<SM::Request>, SM::Request)>,
+}
+
+type Ctx = NodeContext<PaxosTimer>;
+type ClientMessage<R> = Message<PaxosMessage<R>>;
+
+impl<SM: StateMachine> Paxos<SM> {
+    pub fn state(&self) -> &SM {
+        &self.state
+    }
+
+    /// The leader this node knows of, itself while it leads.
+    pub fn leader(&self) -> Option<&str> {
+        self.leader_id.as_deref()
+    }
+
+    fn majority(ctx: &Ctx) -> usize {
+        ctx.node_ids.len() / 2 + 1
+    }
+
+    fn reset_election_timer(&self, ctx: &mut Ctx) {
+        let timeout = ctx
+            .rng()
+            .gen_range(ELECTION_TIMEOUT_MIN..ELECTION_TIMEOUT_MAX);
+        ctx.timers.once(PaxosTimer::Election, timeout);
+    }
+
+    /// Sends `payload` to `dest`, handling it right away if that is us.
+    fn deliver(
+        &mut self,
+        dest: &str,
+        payload: PaxosPayload<SM::Request>,
+        ctx: &mut Ctx,
+    ) -> anyhow::Result<()> {
+        if dest == ctx.node_id {
+            let from = ctx.node_id.clone();
+            self.on_paxos(&from, payload, ctx)
+        } else {
+            ctx.send(dest, PaxosMessage::<SM::Request>::Paxos(payload))
+        }
+    }
+
+    fn broadcast(
+        &mut self,
+        payload: PaxosPayload<SM::Request>,
+        ctx: &mut Ctx,
+    ) -> anyhow::Result<()> {
+        for node in ctx.node_ids.clone() {
+            self.deliver(&node, payload.clone(), ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Gives up leading or campaigning once a higher ballot shows up.
+    fn observe_ballot(&mut self, ballot: &Ballot, ctx: &mut Ctx) {
+        if *ballot <= self.ballot || self.role == Role::Follower {
+            return;
+        }
+        if self.role == Role::Leader {
+            info!("stepping down for {:?}", ballot);
+            ctx.timers.cancel(&PaxosTimer::Heartbeat);
+            // A majority may have accepted them, so the outcome is unknown.
+            for (request, _) in std::mem::take(&mut self.waiting).into_values() {
+                let _ = ctx.reply_error(&request, ErrorCode::Timeout, "leadership lost");
+            }
+        }
+        self.role = Role::Follower;
+        self.leader_id = None;
+        self.reset_election_timer(ctx);
+    }
+
+    fn campaign(&mut self, ctx: &mut Ctx) -> anyhow::Result<()> {
+        self.role = Role::Candidate;
+        self.leader_id = None;
+        self.ballot = Ballot {
+            round: self.ballot.round.max(self.promised.round) + 1,
+            node: ctx.node_id.clone(),
+        };
+        self.promises.clear();
+        self.reset_election_timer(ctx);
+        info!("preparing {:?}", self.ballot);
+        self.broadcast(
+            PaxosPayload::Prepare {
+                ballot: self.ballot.clone(),
+                from_slot: self.applied,
+            },
+            ctx,
+        )
+    }
+
+    /// Takes over every slot a majority reported on: the value accepted with
+    /// the highest ballot is proposed again, gaps are filled with no-ops.
+    fn lead(&mut self, ctx: &mut Ctx) -> anyhow::Result<()> {
+        info!("leading with {:?}", self.ballot);
+        self.role = Role::Leader;
+        self.leader_id = Some(ctx.node_id.clone());
+        ctx.timers.cancel(&PaxosTimer::Election);
+        ctx.timers.every(PaxosTimer::Heartbeat, HEARTBEAT_INTERVAL);
+
+        let mut highest: BTreeMap<usize, Accepted<SM::Request>> = BTreeMap::new();
+        for accepted in self.promises.drain().flat_map(|(_, a)| a) {
+            let keep = highest
+                .get(&accepted.slot)
+                .is_some_and(|a| a.ballot >= accepted.ballot);
+            if !keep {
+                highest.insert(accepted.slot, accepted);
+            }
+        }
+        let end = highest
+            .keys()
+            .chain(self.decided.keys())
+            .max()
+            .map_or(self.applied, |slot| slot + 1)
+            .max(self.applied);
+        self.next_slot = end;
+        self.proposals.clear();
+        self.votes.clear();
+        for slot in self.applied..end {
+            if self.decided.contains_key(&slot) {
+                continue;
+            }
+            let request = highest.remove(&slot).and_then(|a| a.request);
+            self.propose(slot, request, ctx)?;
+        }
+        self.heartbeat(ctx)
+    }
+
+    fn propose(
+        &mut self,
+        slot: usize,
+        request: Option<SM::Request>,
+        ctx: &mut Ctx,
+    ) -> anyhow::Result<()> {
+        self.proposals.insert(slot, request.clone());
+        self.broadcast(
+            PaxosPayload::Accept {
+                ballot: self.ballot.clone(),
+                slot,
+                request,
+            },
+            ctx,
+        )
+    }
+
+    /// Sends every follower the leader's progress, and asks again for the
+    /// oldest slots still undecided, whose `accept` or `accepted` may have
+    /// been lost. Followers that missed a `decide` catch up through their
+    /// `heartbeat_ok`.
+    fn heartbeat(&mut self, ctx: &mut Ctx) -> anyhow::Result<()> {
+        let mut undecided: Vec<usize> = self.proposals.keys().copied().collect();
+        undecided.sort_unstable();
+        for slot in undecided.into_iter().take(MAX_CATCH_UP) {
+            let request = self.proposals[&slot].clone();
+            self.broadcast(
+                PaxosPayload::Accept {
+                    ballot: self.ballot.clone(),
+                    slot,
+                    request,
+                },
+                ctx,
+            )?;
+        }
+        for peer in ctx.peers().cloned().collect::<Vec<_>>() {
+            ctx.send(
+                peer,
+                PaxosMessage::<SM::Request>::Paxos(PaxosPayload::Heartbeat {
+                    ballot: self.ballot.clone(),
+                    applied: self.applied,
+                }),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Applies every decided slot in order and answers the clients waiting
+    /// for them. A client whose slot was decided with something else, a
+    /// no-op an earlier leader filled it with say, is proposed a new slot
+    /// while we lead and told its request timed out otherwise.
+    fn decide(&mut self, decided: Vec<Decided<SM::Request>>, ctx: &mut Ctx) -> anyhow::Result<()> {
+        for Decided { slot, request } in decided {
+            if slot >= self.applied {
+                self.decided.insert(slot, request);
+            }
+        }
+        while let Some(request) = self.decided.get(&self.applied) {
+            let result = request.as_ref().map(|r| self.state.apply(r));
+            let waiting = self.waiting.remove(&self.applied);
+            let applied = self.applied;
+            self.applied += 1;
+            let Some((client, proposed)) = waiting else {
+                continue;
+            };
+            match result {
+                Some(result) if self.decided[&applied].as_ref() == Some(&proposed) => {
+                    match result {
+                        Ok(response) => ctx.reply(&client, response)?,
+                        Err(error) => ctx.reply(&client, error)?,
+                    }
+                }
+                _ if self.role == Role::Leader => self.accept_client(client, proposed, ctx)?,
+                _ => ctx.reply_error(&client, ErrorCode::Timeout, "slot taken")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Proposes `payload` in the next free slot, to answer `client` once it
+    /// is decided.
+    fn accept_client(
+        &mut self,
+        client: ClientMessage<SM::Request>,
+        payload: SM::Request,
+        ctx: &mut Ctx,
+    ) -> anyhow::Result<()> {
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.waiting.insert(slot, (client, payload.clone()));
+        self.propose(slot, Some(payload), ctx)
+    }
+
+    fn on_client(
+        &mut self,
+        request: Message<PaxosMessage<SM::Request>>,
+        ctx: &mut Ctx,
+    ) -> anyhow::Result<()> {
+        let PaxosMessage::Client(payload) = request.body.payload.clone() else {
+            return Ok(());
+        };
+        match self.role {
+            Role::Leader => self.accept_client(request, payload, ctx),
+            // A request forwarded by a peer that thinks we lead is not
+            // passed on again, so it cannot go around in circles.
+            _ => match self
+                .leader_id
+                .clone()
+                .filter(|_| !ctx.node_ids.contains(&request.src))
+            {
+                Some(leader) => ctx.call_with(
+                    leader,
+                    PaxosMessage::<SM::Request>::Client(payload),
+                    RetryPolicy::once(FORWARD_TIMEOUT),
+                    move |ctx, reply: anyhow::Result<Message<SM::Response>>| match reply {
+                        Ok(reply) => ctx.reply(&request, reply.body.payload),
+                        Err(error) => ctx.reply(&request, Error::from(error)),
+                    },
+                ),
+                None => ctx.reply_error(
+                    &request,
+                    ErrorCode::TemporarilyUnavailable,
+                    "no leader known",
+                ),
+            },
+        }
+    }
+
+    fn on_paxos(
+        &mut self,
+        from: &str,
+        payload: PaxosPayload<SM::Request>,
+        ctx: &mut Ctx,
+    ) -> anyhow::Result<()> {
+        match payload {
+            PaxosPayload::Prepare { ballot, from_slot } => {
+                self.observe_ballot(&ballot, ctx);
+                let reply = if ballot >= self.promised {
+                    self.promised = ballot.clone();
+                    PaxosPayload::Promise {
+                        ballot,
+                        accepted: self
+                            .accepted
+                            .range(from_slot..)
+                            .map(|(_, a)| a.clone())
+                            .collect(),
+                    }
+                } else {
+                    PaxosPayload::Nack {
+                        ballot: self.promised.clone(),
+                    }
+                };
+                self.deliver(from, reply, ctx)?;
+            }
+            PaxosPayload::Promise { ballot, accepted } => {
+                if self.role == Role::Candidate && ballot == self.ballot {
+                    self.promises.insert(from.to_string(), accepted);
+                    if self.promises.len() >= Self::majority(ctx) {
+                        self.lead(ctx)?;
+                    }
+                }
+            }
+            PaxosPayload::Accept {
+                ballot,
+                slot,
+                request,
+            } => {
+                self.observe_ballot(&ballot, ctx);
+                let reply = if ballot >= self.promised {
+                    self.promised = ballot.clone();
+                    self.accepted.insert(
+                        slot,
+                        Accepted {
+                            slot,
+                            ballot: ballot.clone(),
+                            request,
+                        },
+                    );
+                    PaxosPayload::Accepted { ballot, slot }
+                } else {
+                    PaxosPayload::Nack {
+                        ballot: self.promised.clone(),
+                    }
+                };
+                self.deliver(from, reply, ctx)?;
+            }
+            PaxosPayload::Accepted { ballot, slot } => {
+                if self.role != Role::Leader || ballot != self.ballot {
+                    return Ok(());
+                }
+                let votes = self.votes.entry(slot).or_default();
+                votes.insert(from.to_string());
+                if votes.len() >= Self::majority(ctx) {
+                    self.votes.remove(&slot);
+                    if let Some(request) = self.proposals.remove(&slot) {
+                        self.broadcast(
+                            PaxosPayload::Decide {
+                                decided: vec![Decided { slot, request }],
+                            },
+                            ctx,
+                        )?;
+                    }
+                }
+            }
+            PaxosPayload::Nack { ballot } => self.observe_ballot(&ballot, ctx),
+            PaxosPayload::Decide { decided } => self.decide(decided, ctx)?,
+            PaxosPayload::Heartbeat { ballot, applied } => {
+                if ballot < self.promised {
+                    return Ok(());
+                }
+                self.observe_ballot(&ballot, ctx);
+                self.promised = ballot;
+                self.leader_id = Some(from.to_string());
+                self.last_heartbeat = Some(ctx.now());
+                self.reset_election_timer(ctx);
+                if self.applied < applied {
+                    self.deliver(
+                        from,
+                        PaxosPayload::HeartbeatOk {
+                            applied: self.applied,
+                        },
+                        ctx,
+                    )?;
+                }
+            }
+            PaxosPayload::HeartbeatOk { applied } => {
+                let decided = self
+                    .decided
+                    .range(applied..)
+                    .take(MAX_CATCH_UP)
+                    .map(|(slot, request)| Decided {
+                        slot: *slot,
+                        request: request.clone(),
+                    })
+                    .collect();
+                self.deliver(from, PaxosPayload::Decide { decided }, ctx)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<SM: StateMachine> Elected for Paxos<SM> {
+    fn leading(&self) -> Option<u64> {
+        (self.role == Role::Leader).then_some(self.ballot.round)
+    }
+}
+
+impl<SM: StateMachine> Node<PaxosMessage<SM::Request>, (), PaxosTimer> for Paxos<SM> {
+    fn new(_state: (), ctx: &mut Ctx) -> Self {
+        let node = Paxos {
+            state: SM::default(),
+            role: Role::Follower,
+            leader_id: None,
+            last_heartbeat: None,
+            ballot: Ballot::default(),
+            promised: Ballot::default(),
+            accepted: BTreeMap::new(),
+            decided: BTreeMap::new(),
+            applied: 0,
+            promises: HashMap::new(),
+            next_slot: 0,
+            proposals: HashMap::new(),
+            votes: HashMap::new(),
+            waiting: BTreeMap::new(),
+        };
+        node.reset_election_timer(ctx);
+        node
+    }
+
+    fn handle(
+        &mut self,
+        message: Message<PaxosMessage<SM::Request>>,
+        ctx: &mut Ctx,
+    ) -> anyhow::Result<()> {
+        match message.body.payload {
+            PaxosMessage::Client(_) => self.on_client(message, ctx),
+            PaxosMessage::Paxos(payload) => self.on_paxos(&message.src, payload, ctx),
+        }
+    }
+
+    fn on_timer(&mut self, timer: PaxosTimer, ctx: &mut Ctx) -> anyhow::Result<()> {
+        match timer {
+            PaxosTimer::Election => {
+                let leader_alive = self
+                    .last_heartbeat
+                    .is_some_and(|at| ctx.now().duration_since(at) < ELECTION_TIMEOUT_MIN);
+                if self.role == Role::Leader || leader_alive {
+                    self.reset_election_timer(ctx);
+                    return Ok(());
+                }
+                self.campaign(ctx)
+            }
+            PaxosTimer::Heartbeat => self.heartbeat(ctx),
+        }
+    }
+}
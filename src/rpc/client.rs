@@ -0,0 +1,147 @@
+use std::{
+    fmt::{self, Debug, Display},
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    clock::Clock,
+    log,
+    rpc::retry::RetryPolicy,
+    utils::await_event::{ExpectedMessages, Expired, ResponseHandle, Route, Slot, Waiter},
+    Message,
+};
+
+/// Why a request sent through `Rpc` did not get a reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// Every attempt allowed by the `RetryPolicy` timed out.
+    Timeout { msg_id: usize, attempts: usize },
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Timeout { msg_id, attempts } => write!(
+                f,
+                "request {} timed out after {} attempt(s)",
+                msg_id, attempts
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Sends requests and matches their replies by `in_reply_to`.
+///
+/// Cloning is cheap: every clone shares the same table of outstanding
+/// requests with `main_loop` and the stdin reader.
+#[derive(Clone, Default)]
+pub struct Rpc {
+    expected: Arc<Mutex<ExpectedMessages>>,
+    clock: Clock,
+}
+
+impl Rpc {
+    /// An `Rpc` timing requests out on `clock`.
+    pub fn new(clock: Clock) -> Self {
+        Rpc {
+            expected: Arc::default(),
+            clock,
+        }
+    }
+
+    /// Sends `message` and returns a handle that resolves with its reply, or
+    /// with `RpcError::Timeout` once `policy` gives up. An `error` reply
+    /// resolves it with the `error::Error` it carries.
+    pub fn call<Q, R>(
+        &self,
+        message: &Message<Q>,
+        policy: RetryPolicy,
+        out: &mut impl Write,
+    ) -> anyhow::Result<ResponseHandle<R>>
+    where
+        Q: Serialize + Debug,
+        R: DeserializeOwned + Serialize + Debug,
+    {
+        let slot = Arc::new(Slot::default());
+        self.expect(message, Waiter::Handle(slot.clone()), policy)?;
+        message.send(out)?;
+        Ok(ResponseHandle::new(slot))
+    }
+
+    /// Starts waiting for the reply to `message` on behalf of a callback
+    /// kept by the caller, returning its `msg_id`. `message` still has to
+    /// be sent.
+    pub(crate) fn expect_callback<Q: Serialize + Debug>(
+        &self,
+        message: &Message<Q>,
+        policy: RetryPolicy,
+    ) -> anyhow::Result<usize> {
+        self.expect(message, Waiter::Callback, policy)
+    }
+
+    /// The earliest point at which `expire` has something to do.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.expected.lock().unwrap().next_deadline()
+    }
+
+    /// Resends requests whose backoff is over and fails the ones that ran
+    /// out of retries. Returns the callback requests that gave up.
+    pub fn expire(
+        &self,
+        now: Instant,
+        out: &mut dyn Write,
+        rng: &mut impl Rng,
+    ) -> anyhow::Result<Vec<(usize, RpcError)>> {
+        let expired = self.expected.lock().unwrap().expire(now, rng);
+        let mut timed_out = Vec::new();
+        for expired in expired {
+            match expired {
+                Expired::Resend(request) => {
+                    log::message("out", &request);
+                    out.write_all(request.as_bytes())?;
+                    out.write_all(b"\n")?;
+                }
+                Expired::TimedOut(msg_id, error) => timed_out.push((msg_id, error)),
+            }
+        }
+        Ok(timed_out)
+    }
+
+    /// Hands `message` to whoever waits for it, as the stdin reader does
+    /// with every line it reads.
+    pub fn route(&self, message: Message<Value>) -> Route {
+        self.expected.lock().unwrap().route(message)
+    }
+
+    /// Stops waiting for the reply to the callback request `msg_id`, telling
+    /// whether it was still waiting.
+    pub(crate) fn take_callback(&self, msg_id: usize) -> bool {
+        self.expected.lock().unwrap().take_callback(msg_id)
+    }
+
+    fn expect<Q: Serialize + Debug>(
+        &self,
+        message: &Message<Q>,
+        waiter: Waiter,
+        policy: RetryPolicy,
+    ) -> anyhow::Result<usize> {
+        let msg_id = message
+            .body
+            .msg_id
+            .ok_or_else(|| anyhow::anyhow!("rpc request without msg_id: {:?}", message))?;
+        let request = serde_json::to_string(message)?;
+        self.expected
+            .lock()
+            .unwrap()
+            .expect(msg_id, waiter, request, policy, self.clock.now());
+        Ok(msg_id)
+    }
+}
@@ -1 +1,3 @@
+pub mod client;
+pub mod retry;
 pub mod stdin_handler;
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// How long `Rpc` waits for a reply and how often it resends the request.
+///
+/// After an attempt times out the request is resent after an exponential
+/// backoff, `backoff * 2^attempt` capped at `max_backoff`, scaled by a random
+/// factor in `1 ± jitter`. Once `retries` resends have timed out as well, the
+/// caller gets `RpcError::Timeout`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    pub retries: usize,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Duration::from_secs(1),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A single attempt that fails after `timeout`.
+    pub fn once(timeout: Duration) -> Self {
+        RetryPolicy {
+            timeout,
+            retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before resending after `attempt` (starting at 0) timed out,
+    /// jittered with `rng`.
+    pub fn backoff(&self, attempt: usize, rng: &mut impl Rng) -> Duration {
+        let exp = self
+            .backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff);
+        let jitter = self.jitter.min(1.0);
+        if jitter <= 0.0 {
+            return exp;
+        }
+        exp.mul_f64(rng.gen_range(1.0 - jitter..1.0 + jitter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let delays: Vec<u64> = (0..6)
+            .map(|attempt| policy.backoff(attempt, &mut rng).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.backoff(usize::MAX, &mut rng), policy.max_backoff);
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = RetryPolicy::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        for attempt in 0..5 {
+            let exp = policy
+                .backoff
+                .saturating_mul(1 << attempt)
+                .min(policy.max_backoff);
+            for _ in 0..100 {
+                let delay = policy.backoff(attempt, &mut rng);
+                assert!(delay >= exp.mul_f64(1.0 - policy.jitter));
+                assert!(delay <= exp.mul_f64(1.0 + policy.jitter));
+            }
+        }
+
+        // Jitter above 1 is capped, so a delay never goes negative.
+        let wild = RetryPolicy {
+            jitter: 5.0,
+            ..policy
+        };
+        for _ in 0..100 {
+            assert!(wild.backoff(0, &mut rng) <= wild.backoff.mul_f64(2.0));
+        }
+    }
+}
@@ -1,23 +1,78 @@
-use std::{thread, io, sync::mpsc::Sender};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
 use std::fmt::Debug;
-use serde::{Serialize, Deserialize};
+use std::{
+    io::{self, BufRead},
+    sync::mpsc::Sender,
+    thread::{self, JoinHandle},
+};
 
-use crate::Message;
+use crate::{log, rpc::client::Rpc, utils::await_event::Route, Message};
 
-pub fn message_handler<'a, P: Deserialize<'a> + Debug + Clone + Serialize + Send + 'static>(sn: Sender<Message<P>>, chain: &[Box<dyn Fn(String) -> bool>]) {
-    thread::spawn(move || {
-        let stdin = io::stdin();
-        for i in stdin.lines() {
-            let i = i.unwrap();
-			for z in chain {
-				if z(i) {
-					continue;
-				}
-			}
-			let i: Message::<P> = serde_json::from_str(i.as_str()).unwrap();
-            eprintln!("in: {:?}", i);
+/// What the stdin reader hands over to `main_loop`.
+pub enum Event<P: Serialize + Debug> {
+    /// A message for `Node::handle`.
+    Message(Message<P>),
+    /// A reply to a request sent with `NodeContext::call_with`.
+    Reply(Message<Value>),
+    /// A message that is not a `Message<P>`, for `Node::handle_unknown`.
+    /// A line that is not even JSON is passed on as a string.
+    Unknown(Value),
+}
 
-            sn.send(i.clone()).unwrap();
+/// Spawns the thread that parses messages from stdin and forwards them to
+/// `sn`. Replies that a `ResponseHandle` waits for are delivered straight to
+/// it, so a node blocked on one still gets its answer.
+///
+/// The thread exits when stdin is closed or the receiver is dropped, which
+/// disconnects the channel and lets `main_loop` finish.
+pub fn message_handler<P: DeserializeOwned + Serialize + Debug + Send + 'static>(
+    sn: Sender<Event<P>>,
+    rpc: Rpc,
+) -> JoinHandle<anyhow::Result<()>> {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Some(event) = classify(&line, &rpc)? else {
+                continue;
+            };
+            if sn.send(event).is_err() {
+                break;
+            }
         }
-    });
+        Ok(())
+    })
+}
+
+/// Turns an inbound `line` into the event `main_loop` dispatches, or `None`
+/// if it was a reply that went straight to a `ResponseHandle`.
+pub(crate) fn classify<P: DeserializeOwned + Serialize + Debug>(
+    line: &str,
+    rpc: &Rpc,
+) -> anyhow::Result<Option<Event<P>>> {
+    log::message("in", line);
+    let event = match parse(line) {
+        Ok(message) => match rpc.route(message) {
+            Route::Delivered => return Ok(None),
+            Route::Callback(reply) => Event::Reply(reply),
+            Route::Unexpected(message) => {
+                let raw = serde_json::to_value(&message)?;
+                match message.decode() {
+                    Ok(message) => Event::Message(message),
+                    Err(_) => Event::Unknown(raw),
+                }
+            }
+        },
+        Err(raw) => Event::Unknown(raw),
+    };
+    Ok(Some(event))
+}
+
+/// Parses `line` into a message, or hands back whatever could be made of it.
+fn parse(line: &str) -> Result<Message<Value>, Value> {
+    let value: Value = serde_json::from_str(line).map_err(|_| Value::String(line.to_string()))?;
+    serde_json::from_value(value.clone()).map_err(|_| value)
 }
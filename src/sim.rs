@@ -0,0 +1,439 @@
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap},
+    fmt::Debug,
+    io::{self, Write},
+    marker::PhantomData,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    clock::Clock,
+    context::NodeContext,
+    dispatch,
+    faults::{Fault, Faults, Latency, Partition, Scheduled, Timeline},
+    log,
+    rpc::stdin_handler::classify,
+    tick, trace, warn, Init, Message, Node,
+};
+
+/// The id requests sent with `Simulation::request` come from.
+pub const CLIENT_ID: &str = "c1";
+
+/// A node of a cluster that elects a leader, for `Simulation::leader` and
+/// friends.
+pub trait Elected {
+    /// The term, or ballot round, the node leads in, if it thinks it leads.
+    fn leading(&self) -> Option<u64>;
+}
+
+/// What a node wrote, shared between its context and the simulation.
+#[derive(Clone, Default)]
+struct Outbox(Rc<RefCell<Vec<u8>>>);
+
+impl Outbox {
+    fn take_lines(&self) -> Vec<String> {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Write for Outbox {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct SimNode<N, T> {
+    node: N,
+    ctx: NodeContext<T>,
+    outbox: Outbox,
+}
+
+/// A message on the network. `seq` keeps messages due at the same time in
+/// the order they were sent.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct InFlight {
+    at: Instant,
+    seq: u64,
+    dest: String,
+    line: String,
+}
+
+/// A message as it was delivered, `at` since the simulation started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub at: Duration,
+    pub line: String,
+}
+
+/// Runs a cluster of `N` nodes in one thread on a virtual clock.
+///
+/// Nodes are created as `main_loop` would create them, named `n0`, `n1`,
+/// ..., and get the same events: every message they write goes out on a
+/// virtual network, where it takes a latency picked from `latency` to arrive,
+/// and their timers and rpc retries fire as the clock reaches them. The
+/// simulation always runs the earliest event next and jumps the clock to it.
+/// A fault `Timeline` set with `set_faults` decides what else the network
+/// does to messages between nodes.
+///
+/// Every random choice, of the network and of the nodes through
+/// `NodeContext::rng`, is drawn from generators seeded with `seed`, so a seed
+/// replays the same run, message for message. Nodes must not block on a
+/// `ResponseHandle`, nothing arrives while they wait.
+pub struct Simulation<S, P, N, T = ()> {
+    /// How long a message takes to arrive, unless a fault says otherwise.
+    pub latency: Latency,
+    faults: Faults,
+    clock: Clock,
+    start: Instant,
+    rng: StdRng,
+    nodes: BTreeMap<String, SimNode<N, T>>,
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    sent: u64,
+    client_msg_id: usize,
+    received: Vec<Message<Value>>,
+    journal: Vec<Delivery>,
+    _node: PhantomData<fn(S, P)>,
+}
+
+impl<S, P, N, T> Simulation<S, P, N, T>
+where
+    S: Clone,
+    P: DeserializeOwned + Serialize + Debug,
+    N: Node<P, S, T>,
+    T: Clone + PartialEq + Debug,
+{
+    /// Starts `nodes` nodes, each created from a clone of `state`.
+    pub fn new(nodes: usize, seed: u64, state: S) -> anyhow::Result<Self> {
+        let start = Instant::now();
+        let node_ids: Vec<String> = (0..nodes).map(|i| format!("n{}", i)).collect();
+        let mut sim = Simulation {
+            latency: Latency::default(),
+            faults: Faults::new(Timeline::default(), node_ids.clone()),
+            clock: Clock::starting_at(start),
+            start,
+            rng: StdRng::seed_from_u64(seed),
+            nodes: BTreeMap::new(),
+            in_flight: BinaryHeap::new(),
+            sent: 0,
+            client_msg_id: 0,
+            received: Vec::new(),
+            journal: Vec::new(),
+            _node: PhantomData,
+        };
+
+        for node_id in &node_ids {
+            let init = Init {
+                node_id: node_id.clone(),
+                node_ids: node_ids.clone(),
+            };
+            let outbox = Outbox::default();
+            let rng = StdRng::seed_from_u64(sim.rng.gen());
+            let mut ctx =
+                NodeContext::with_clock(init, Box::new(outbox.clone()), sim.clock.clone(), rng);
+            log::set_node_id(node_id);
+            let node = N::new(state.clone(), &mut ctx);
+            for line in outbox.take_lines() {
+                sim.send(line);
+            }
+            sim.nodes
+                .insert(node_id.clone(), SimNode { node, ctx, outbox });
+        }
+        Ok(sim)
+    }
+
+    /// Replaces the faults, with times counted from the start of the
+    /// simulation.
+    pub fn set_faults(&mut self, timeline: Timeline) {
+        let node_ids = self.nodes.keys().cloned().collect();
+        self.faults = Faults::new(timeline, node_ids);
+    }
+
+    /// Cuts `node_id` off from every other node from now on.
+    pub fn isolate(&mut self, node_id: &str) {
+        let others = self.others(node_id).into_iter().collect();
+        let groups = vec![BTreeSet::from([node_id.to_string()]), others];
+        self.set_faults(Timeline {
+            faults: vec![Scheduled {
+                fault: Fault::Partition(Partition::Groups(groups)),
+                from: self.elapsed(),
+                until: None,
+            }],
+        });
+    }
+
+    /// Lifts every fault.
+    pub fn heal(&mut self) {
+        self.set_faults(Timeline::default());
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = &String> {
+        self.nodes.keys()
+    }
+
+    /// Every node but `node_id`.
+    pub fn others(&self, node_id: &str) -> Vec<String> {
+        self.node_ids()
+            .filter(|id| *id != node_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn node(&self, node_id: &str) -> Option<&N> {
+        self.nodes.get(node_id).map(|n| &n.node)
+    }
+
+    /// Virtual time since the simulation started.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now() - self.start
+    }
+
+    /// Every message delivered so far, nodes' and clients' alike.
+    pub fn journal(&self) -> &[Delivery] {
+        &self.journal
+    }
+
+    /// Every message delivered to something that is not a node, such as
+    /// `CLIENT_ID`, in the order they arrived.
+    pub fn received(&self) -> &[Message<Value>] {
+        &self.received
+    }
+
+    /// The reply to the request `msg_id`, once it arrived.
+    pub fn reply(&self, msg_id: usize) -> Option<&Message<Value>> {
+        self.received
+            .iter()
+            .find(|m| m.body.in_reply_to == Some(msg_id))
+    }
+
+    /// Sends `payload` to `node_id` from `CLIENT_ID`, returning the `msg_id`
+    /// its reply will answer.
+    pub fn request(
+        &mut self,
+        node_id: &str,
+        payload: impl Serialize + Debug,
+    ) -> anyhow::Result<usize> {
+        let message = Message::new(
+            CLIENT_ID.to_string(),
+            node_id.to_string(),
+            payload,
+            &mut self.client_msg_id,
+        );
+        self.send(serde_json::to_string(&message)?);
+        Ok(self.client_msg_id)
+    }
+
+    /// Sends a request and runs until its reply arrives, for at most
+    /// `timeout`.
+    pub fn call(
+        &mut self,
+        node_id: &str,
+        payload: impl Serialize + Debug,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<Message<Value>>> {
+        let msg_id = self.request(node_id, payload)?;
+        let end = self.clock.now() + timeout;
+        while self.reply(msg_id).is_none() && self.next_event().is_some_and(|at| at <= end) {
+            self.step()?;
+        }
+        Ok(self.reply(msg_id).cloned())
+    }
+
+    /// `call`, for just the payload of the reply.
+    pub fn call_payload(
+        &mut self,
+        node_id: &str,
+        payload: impl Serialize + Debug,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<Value>> {
+        let reply = self.call(node_id, payload, timeout)?;
+        Ok(reply.map(|reply| reply.body.payload))
+    }
+
+    /// Sends `payloads` to `node_ids` in turn, one every `spacing`, runs on
+    /// for `settle` and returns the payload of every reply that arrived, in
+    /// the order of the requests.
+    pub fn request_all<Q: Serialize + Debug>(
+        &mut self,
+        node_ids: &[impl AsRef<str>],
+        payloads: impl IntoIterator<Item = Q>,
+        spacing: Duration,
+        settle: Duration,
+    ) -> anyhow::Result<Vec<Option<Value>>> {
+        let mut msg_ids = Vec::new();
+        for (i, payload) in payloads.into_iter().enumerate() {
+            let node_id = node_ids[i % node_ids.len()].as_ref();
+            msg_ids.push(self.request(node_id, payload)?);
+            self.run_for(spacing)?;
+        }
+        self.run_for(settle)?;
+        let replies = msg_ids
+            .into_iter()
+            .map(|msg_id| self.reply(msg_id).map(|reply| reply.body.payload.clone()))
+            .collect();
+        Ok(replies)
+    }
+
+    /// Runs everything due within `duration`, then moves the clock to its
+    /// end.
+    pub fn run_for(&mut self, duration: Duration) -> anyhow::Result<()> {
+        let end = self.clock.now() + duration;
+        while self.next_event().is_some_and(|at| at <= end) {
+            self.step()?;
+        }
+        self.clock.advance_to(end);
+        Ok(())
+    }
+
+    /// Runs the earliest event: delivers the next message, or runs the
+    /// timers and rpc deadlines of the first node with some due. Returns
+    /// false if there is nothing left to run.
+    pub fn step(&mut self) -> anyhow::Result<bool> {
+        let Some(at) = self.next_event() else {
+            return Ok(false);
+        };
+        self.clock.advance_to(at);
+        if self.in_flight.peek().is_some_and(|Reverse(m)| m.at <= at) {
+            let Reverse(message) = self.in_flight.pop().unwrap();
+            self.deliver(message)?;
+        } else {
+            let node_id = self
+                .nodes
+                .iter()
+                .find(|(_, n)| n.ctx.next_deadline().is_some_and(|d| d <= at))
+                .map(|(id, _)| id.clone())
+                .unwrap();
+            self.run(&node_id, None)?;
+        }
+        Ok(true)
+    }
+
+    fn next_event(&self) -> Option<Instant> {
+        let message = self.in_flight.peek().map(|Reverse(m)| m.at);
+        let deadline = self
+            .nodes
+            .values()
+            .filter_map(|n| n.ctx.next_deadline())
+            .min();
+        message.into_iter().chain(deadline).min()
+    }
+
+    /// Puts a line a node or client wrote on the network.
+    fn send(&mut self, line: String) {
+        let message = match serde_json::from_str::<Message<Value>>(&line) {
+            Ok(message) => message,
+            Err(error) => {
+                warn!("dropping unroutable line {:?}: {}", line, error);
+                return;
+            }
+        };
+        let now = self.clock.now();
+        let fate = self.faults.fate(
+            now - self.start,
+            &message.src,
+            &message.dest,
+            &self.latency,
+            &mut self.rng,
+        );
+        trace!("{} -> {}: {}", message.src, message.dest, fate);
+        for delay in fate.delays {
+            self.sent += 1;
+            self.in_flight.push(Reverse(InFlight {
+                at: now + delay,
+                seq: self.sent,
+                dest: message.dest.clone(),
+                line: line.clone(),
+            }));
+        }
+    }
+
+    fn deliver(&mut self, message: InFlight) -> anyhow::Result<()> {
+        self.journal.push(Delivery {
+            at: message.at - self.start,
+            line: message.line.clone(),
+        });
+        if self.nodes.contains_key(&message.dest) {
+            self.run(&message.dest, Some(&message.line))
+        } else {
+            self.received.push(serde_json::from_str(&message.line)?);
+            Ok(())
+        }
+    }
+
+    /// Has `node_id` handle `line`, if any, and whatever else is due, then
+    /// sends what it wrote.
+    fn run(&mut self, node_id: &str, line: Option<&str>) -> anyhow::Result<()> {
+        let SimNode { node, ctx, outbox } = self.nodes.get_mut(node_id).unwrap();
+        log::set_node_id(node_id);
+        if let Some(event) = line
+            .map(|l| classify::<P>(l, &ctx.rpc))
+            .transpose()?
+            .flatten()
+        {
+            dispatch::<P, S, N, T>(node, event, ctx)?;
+        }
+        tick::<P, S, N, T>(node, ctx)?;
+        for line in outbox.take_lines() {
+            self.send(line);
+        }
+        Ok(())
+    }
+}
+
+impl<S, P, N, T> Simulation<S, P, N, T>
+where
+    S: Clone,
+    P: DeserializeOwned + Serialize + Debug,
+    N: Node<P, S, T> + Elected,
+    T: Clone + PartialEq + Debug,
+{
+    /// The node leading in the highest term, if any does.
+    pub fn leader(&self) -> Option<String> {
+        self.leader_among(self.node_ids())
+    }
+
+    /// Of `node_ids`, the node leading in the highest term, if any does.
+    pub fn leader_among(
+        &self,
+        node_ids: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Option<String> {
+        node_ids
+            .into_iter()
+            .filter_map(|id| {
+                let term = self.node(id.as_ref())?.leading()?;
+                Some((term, id.as_ref().to_string()))
+            })
+            .max()
+            .map(|(_, id)| id)
+    }
+
+    /// Runs until some node leads, for at most `timeout`, and returns it.
+    pub fn run_until_leader(&mut self, timeout: Duration) -> anyhow::Result<String> {
+        let end = self.clock.now() + timeout;
+        loop {
+            if let Some(leader) = self.leader() {
+                return Ok(leader);
+            }
+            if self.next_event().is_none_or(|at| at > end) {
+                anyhow::bail!("no leader within {:?}", timeout);
+            }
+            self.step()?;
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+struct Timer<T> {
+    event: T,
+    deadline: Instant,
+    period: Option<Duration>,
+}
+
+/// The timers of a node, each delivering its event to `Node::on_timer`.
+///
+/// A timer is named by its event: scheduling an event that is equal to one
+/// already scheduled replaces it, so a one-shot timeout can be pushed back by
+/// scheduling it again.
+pub struct Timers<T> {
+    timers: Vec<Timer<T>>,
+    clock: Clock,
+}
+
+impl<T> Default for Timers<T> {
+    fn default() -> Self {
+        Timers::new(Clock::System)
+    }
+}
+
+impl<T> Timers<T> {
+    /// Timers whose delays are measured on `clock`.
+    pub fn new(clock: Clock) -> Self {
+        Timers {
+            timers: Vec::new(),
+            clock,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> Timers<T> {
+    /// Fires `event` every `interval`, starting one `interval` from now.
+    ///
+    /// Panics if `interval` is zero, the timer would be due again as soon as
+    /// it fired.
+    pub fn every(&mut self, event: T, interval: Duration) {
+        assert!(!interval.is_zero(), "periodic timer with a zero interval");
+        self.schedule(event, interval, Some(interval));
+    }
+
+    /// Fires `event` once, `delay` from now.
+    pub fn once(&mut self, event: T, delay: Duration) {
+        self.schedule(event, delay, None);
+    }
+
+    pub fn cancel(&mut self, event: &T) {
+        self.timers.retain(|t| t.event != *event);
+    }
+
+    pub fn is_scheduled(&self, event: &T) -> bool {
+        self.timers.iter().any(|t| t.event == *event)
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.iter().map(|t| t.deadline).min()
+    }
+
+    /// Takes the earliest event due at `now`, rescheduling it if it is
+    /// periodic.
+    pub fn pop_due(&mut self, now: Instant) -> Option<T> {
+        let timers = &mut self.timers;
+        let (i, _) = timers
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.deadline <= now)
+            .min_by_key(|(_, t)| t.deadline)?;
+        match timers[i].period {
+            Some(period) => {
+                timers[i].deadline = now + period;
+                Some(timers[i].event.clone())
+            }
+            None => Some(timers.swap_remove(i).event),
+        }
+    }
+
+    fn schedule(&mut self, event: T, delay: Duration, period: Option<Duration>) {
+        self.timers.retain(|t| t.event != event);
+        self.timers.push(Timer {
+            event,
+            deadline: self.clock.now() + delay,
+            period,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timers() -> (Timers<&'static str>, Clock, Instant) {
+        let start = Instant::now();
+        let clock = Clock::starting_at(start);
+        (Timers::new(clock.clone()), clock, start)
+    }
+
+    #[test]
+    fn due_timers_pop_earliest_first() {
+        let (mut timers, _, start) = timers();
+        timers.once("late", Duration::from_millis(30));
+        timers.once("early", Duration::from_millis(10));
+        timers.once("middle", Duration::from_millis(20));
+        assert_eq!(
+            timers.next_deadline(),
+            Some(start + Duration::from_millis(10))
+        );
+
+        assert_eq!(timers.pop_due(start + Duration::from_millis(5)), None);
+        let now = start + Duration::from_millis(25);
+        assert_eq!(timers.pop_due(now), Some("early"));
+        assert_eq!(timers.pop_due(now), Some("middle"));
+        assert_eq!(timers.pop_due(now), None);
+        assert_eq!(
+            timers.next_deadline(),
+            Some(start + Duration::from_millis(30))
+        );
+    }
+
+    #[test]
+    fn scheduling_again_replaces_and_cancel_removes() {
+        let (mut timers, clock, start) = timers();
+        timers.once("timeout", Duration::from_millis(10));
+        clock.advance_to(start + Duration::from_millis(5));
+        timers.once("timeout", Duration::from_millis(10));
+        assert_eq!(timers.pop_due(start + Duration::from_millis(10)), None);
+        assert_eq!(
+            timers.pop_due(start + Duration::from_millis(15)),
+            Some("timeout")
+        );
+        assert!(!timers.is_scheduled(&"timeout"));
+
+        timers.once("timeout", Duration::from_millis(10));
+        timers.every("tick", Duration::from_millis(10));
+        timers.cancel(&"timeout");
+        assert!(!timers.is_scheduled(&"timeout"));
+        assert!(timers.is_scheduled(&"tick"));
+        timers.cancel(&"tick");
+        assert_eq!(timers.next_deadline(), None);
+    }
+
+    #[test]
+    fn periodic_timers_reschedule_from_when_they_fire() {
+        let (mut timers, _, start) = timers();
+        timers.every("tick", Duration::from_millis(10));
+        let now = start + Duration::from_millis(12);
+        assert_eq!(timers.pop_due(now), Some("tick"));
+        assert_eq!(timers.pop_due(now), None);
+        assert_eq!(
+            timers.next_deadline(),
+            Some(now + Duration::from_millis(10))
+        );
+        assert_eq!(
+            timers.pop_due(now + Duration::from_millis(10)),
+            Some("tick")
+        );
+        assert!(timers.is_scheduled(&"tick"));
+    }
+
+    #[test]
+    #[should_panic(expected = "zero interval")]
+    fn zero_intervals_are_rejected() {
+        let (mut timers, _, _) = timers();
+        timers.every("tick", Duration::ZERO);
+    }
+}
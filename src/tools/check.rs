@@ -0,0 +1,57 @@
+use std::{
+    env, fs,
+    io::{self, Read},
+    process::ExitCode,
+};
+
+use dist_system::check::{history::History, linearizable, txn};
+
+const USAGE: &str = "usage: check lin-kv|txn-list-append|txn-rw-register [FILE]
+
+Checks the client operations in a stream of messages, one JSON message per
+line, read from FILE or stdin: lin-kv histories for linearizability,
+transactional ones for the isolation levels they violate.";
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (workload, path) = match args.as_slice() {
+        [workload] => (workload.as_str(), None),
+        [workload, path] => (workload.as_str(), Some(path)),
+        _ => {
+            eprintln!("{}", USAGE);
+            return Ok(ExitCode::from(2));
+        }
+    };
+    let stream = match path {
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            let mut stream = String::new();
+            io::stdin().read_to_string(&mut stream)?;
+            stream
+        }
+    };
+    let history = History::from_messages(&stream);
+
+    let invalid = match workload {
+        "lin-kv" => linearizable::check(&history)?.map(|c| c.to_string()),
+        "txn-list-append" | "txn-rw-register" => {
+            let report = txn::check(&history, workload.parse()?)?;
+            (!report.is_valid()).then(|| report.to_string())
+        }
+        _ => {
+            eprintln!("unknown workload {:?}\n\n{}", workload, USAGE);
+            return Ok(ExitCode::from(2));
+        }
+    };
+    let operations = history.operations().len();
+    match invalid {
+        None => {
+            println!("valid: {} operations", operations);
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(invalid) => {
+            print!("invalid: {}", invalid);
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
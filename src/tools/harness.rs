@@ -0,0 +1,77 @@
+use std::{env, process::ExitCode};
+
+use dist_system::{
+    faults::parse_duration,
+    harness::{self, workload, Config},
+};
+
+const USAGE: &str = "usage: harness WORKLOAD BINARY [OPTION VALUE]...
+
+Runs WORKLOAD against a cluster of BINARY processes and checks the result.
+Workloads are echo, unique-ids, broadcast, g-counter, pn-counter, lin-kv and
+txn-rw-register.
+
+options:
+  --nodes N          nodes in the cluster, 3
+  --clients N        clients sending requests at the same time, 3
+  --time-limit T     how long the clients send requests for, 5s
+  --rate R           requests per second over all clients, 10
+  --timeout T        how long a client waits for a reply, 1s
+  --settle T         how long the cluster settles before final reads, 1s
+  --latency L        5ms, 1ms..10ms or exp 20ms, 1ms..10ms
+  --faults F         a fault timeline, such as \"partition halves from 1s to 3s\"
+  --seed N           seeds the requests and the network, 0
+  --journal FILE     writes every message delivered to FILE, for check
+  --log-dir DIR      writes the stderr of every node to DIR/<node>.log";
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (name, config) = match parse(&args) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return Ok(ExitCode::from(2));
+        }
+    };
+    let mut workload = workload::by_name(name)?;
+    let report = harness::run(&config, workload.as_mut())?;
+    print!("{}", report);
+    Ok(if report.is_valid() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn parse(args: &[String]) -> anyhow::Result<(&str, Config)> {
+    let [name, binary, options @ ..] = args else {
+        return Err(anyhow::anyhow!("missing arguments"));
+    };
+    let mut config = Config {
+        binary: binary.into(),
+        ..Config::default()
+    };
+    for option in options.chunks(2) {
+        let [option, value] = option else {
+            return Err(anyhow::anyhow!("{} without a value", option[0]));
+        };
+        match option.as_str() {
+            "--nodes" => config.nodes = value.parse()?,
+            "--clients" => config.clients = value.parse()?,
+            "--time-limit" => config.time_limit = parse_duration(value)?,
+            "--rate" => config.rate = value.parse()?,
+            "--timeout" => config.timeout = parse_duration(value)?,
+            "--settle" => config.settle = parse_duration(value)?,
+            "--latency" => config.latency = value.parse()?,
+            "--faults" => config.faults = value.parse()?,
+            "--seed" => config.seed = value.parse()?,
+            "--journal" => config.journal = Some(value.into()),
+            "--log-dir" => config.log_dir = Some(value.into()),
+            _ => return Err(anyhow::anyhow!("unknown option {}", option)),
+        }
+    }
+    if config.nodes == 0 || config.rate <= 0.0 {
+        return Err(anyhow::anyhow!("--nodes and --rate have to be positive"));
+    }
+    Ok((name, config))
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, env, str::FromStr};
+
+/// The environment variable `Overlay::from_env` reads.
+pub const TOPOLOGY_ENV: &str = "TOPOLOGY";
+
+/// Which graph a node gossips along.
+///
+/// `Supplied` uses the neighbours from the `topology` message, the others
+/// ignore it and build the graph from `NodeContext::node_ids`, which every node
+/// receives in the same order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overlay {
+    #[default]
+    Supplied,
+    /// The first node talks to everybody, everybody else only to it.
+    Star,
+    /// A tree where every node has up to `branching` children.
+    Tree { branching: usize },
+    /// Every node is linked to `degree` others on a ring with chords.
+    Regular { degree: usize },
+}
+
+impl FromStr for Overlay {
+    type Err = anyhow::Error;
+
+    /// Parses `supplied`, `star`, `tree:<branching>` or `regular:<degree>`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg.parse::<usize>()?)),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("supplied", None) => Ok(Overlay::Supplied),
+            ("star", None) => Ok(Overlay::Star),
+            ("tree", branching) => Ok(Overlay::Tree {
+                branching: branching.unwrap_or(4).max(1),
+            }),
+            ("regular", degree) => Ok(Overlay::Regular {
+                degree: degree.unwrap_or(3).max(1),
+            }),
+            _ => anyhow::bail!("unknown overlay: {}", s),
+        }
+    }
+}
+
+impl Overlay {
+    /// Reads the overlay from `TOPOLOGY`, defaulting to `Supplied`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var(TOPOLOGY_ENV) {
+            Ok(overlay) => overlay.parse(),
+            Err(_) => Ok(Overlay::Supplied),
+        }
+    }
+
+    /// The neighbours of `node_id`, or `None` for `Supplied`, which has to
+    /// wait for the `topology` message.
+    pub fn neighbours(&self, node_id: &str, node_ids: &[String]) -> Option<Vec<String>> {
+        let n = node_ids.len();
+        let i = node_ids.iter().position(|id| id == node_id)?;
+        let mut neighbours: Vec<usize> = match *self {
+            Overlay::Supplied => return None,
+            Overlay::Star if i == 0 => (1..n).collect(),
+            Overlay::Star => vec![0],
+            Overlay::Tree { branching } => {
+                let parent = (i > 0).then(|| (i - 1) / branching);
+                let children = (i * branching + 1..=i * branching + branching).filter(|c| *c < n);
+                parent.into_iter().chain(children).collect()
+            }
+            Overlay::Regular { degree } => {
+                let mut offsets: Vec<usize> = (1..=degree / 2).collect();
+                if !degree.is_multiple_of(2) && n.is_multiple_of(2) {
+                    offsets.push(n / 2);
+                }
+                offsets
+                    .into_iter()
+                    .flat_map(|o| [(i + o) % n, (i + n - o % n) % n])
+                    .collect()
+            }
+        };
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours.retain(|j| *j != i);
+        Some(
+            neighbours
+                .into_iter()
+                .map(|j| node_ids[j].clone())
+                .collect(),
+        )
+    }
+}
+
+/// The nodes a node gossips to.
+#[derive(Debug, Clone)]
+pub struct Neighbours {
+    pub overlay: Overlay,
+    pub node_id: String,
+    pub nodes: Vec<String>,
+}
+
+impl Neighbours {
+    pub fn new(overlay: Overlay, node_id: &str, node_ids: &[String]) -> Self {
+        Neighbours {
+            overlay,
+            node_id: node_id.to_string(),
+            nodes: overlay.neighbours(node_id, node_ids).unwrap_or_default(),
+        }
+    }
+
+    /// Takes the neighbours from a `topology` message if the overlay is
+    /// `Supplied`.
+    pub fn on_topology(&mut self, topology: &HashMap<String, Vec<String>>) {
+        if self.overlay != Overlay::Supplied {
+            return;
+        }
+        self.nodes = topology.get(&self.node_id).cloned().unwrap_or_default();
+        self.nodes.retain(|node| *node != self.node_id);
+    }
+}
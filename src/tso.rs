@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{context::NodeContext, rpc::retry::RetryPolicy};
+
+/// The name of maelstrom's timestamp oracle, as a `dest`.
+pub const TSO_SERVICE: &str = "lin-tso";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum TsoPayload {
+    Ts,
+    TsOk { ts: u64 },
+}
+
+/// A client for the `lin-tso` timestamp oracle.
+///
+/// Timestamps are linearizable: every one is greater than any handed out
+/// before the request was sent, which makes them usable as start and commit
+/// timestamps of snapshot-isolated transactions. Requests are retried
+/// according to `policy`, since a timestamp that is lost is merely skipped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TsoClient {
+    pub policy: RetryPolicy,
+}
+
+impl TsoClient {
+    pub fn new() -> Self {
+        TsoClient::default()
+    }
+
+    /// Gets a fresh timestamp, blocking until it arrives.
+    pub fn ts<T>(&self, ctx: &mut NodeContext<T>) -> anyhow::Result<u64> {
+        let reply = ctx
+            .call::<_, TsoPayload>(TSO_SERVICE, TsoPayload::Ts, self.policy)?
+            .wait(ctx)?;
+        timestamp(reply.body.payload)
+    }
+
+    /// Gets a fresh timestamp and runs `callback` with it, for nodes that
+    /// must not block, such as ones running in a `Simulation`.
+    pub fn ts_with<T, F>(&self, ctx: &mut NodeContext<T>, callback: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut NodeContext<T>, anyhow::Result<u64>) -> anyhow::Result<()> + 'static,
+    {
+        ctx.call_with(
+            TSO_SERVICE,
+            TsoPayload::Ts,
+            self.policy,
+            move |ctx, reply: anyhow::Result<crate::Message<TsoPayload>>| {
+                callback(ctx, reply.and_then(|reply| timestamp(reply.body.payload)))
+            },
+        )
+    }
+}
+
+fn timestamp(reply: TsoPayload) -> anyhow::Result<u64> {
+    match reply {
+        TsoPayload::TsOk { ts } => Ok(ts),
+        reply => Err(anyhow::anyhow!("unexpected tso reply: {:?}", reply)),
+    }
+}
@@ -0,0 +1,287 @@
+use std::{collections::HashMap, env, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorCode};
+
+/// The environment variable `Isolation::from_env` reads.
+pub const TXN_ISOLATION_ENV: &str = "TXN_ISOLATION";
+
+/// How much of other transactions a transaction may observe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isolation {
+    /// Writes take effect as each micro-op runs, so a transaction that
+    /// fails halfway leaves its first writes behind.
+    ReadUncommitted,
+    /// Writes are buffered until the whole transaction succeeded and only
+    /// the last write to every key is installed.
+    #[default]
+    ReadCommitted,
+}
+
+impl FromStr for Isolation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-uncommitted" => Ok(Isolation::ReadUncommitted),
+            "read-committed" => Ok(Isolation::ReadCommitted),
+            _ => Err(anyhow::anyhow!("unknown isolation level {:?}", s)),
+        }
+    }
+}
+
+impl Isolation {
+    /// Reads `TXN_ISOLATION`, read-committed if it is not set.
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var(TXN_ISOLATION_ENV) {
+            Ok(isolation) => isolation.parse(),
+            Err(_) => Ok(Isolation::default()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    #[serde(rename = "r")]
+    Read,
+    #[serde(rename = "w")]
+    Write,
+}
+
+/// A single operation of a transaction, `["r", k, null]` or `["w", k, v]`
+/// on the wire. A read is answered with the value filled in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MicroOp(pub Function, pub u64, pub Option<u64>);
+
+/// Orders the transactions of the whole cluster: a Lamport clock, with the
+/// node id breaking ties.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub counter: u64,
+    pub node: String,
+}
+
+/// The writes of one transaction, replicated as a unit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Committed {
+    pub version: Version,
+    pub writes: Vec<(u64, u64)>,
+}
+
+/// The outcome of `TxnStore::execute`.
+#[derive(Debug)]
+pub struct Executed {
+    /// The micro-ops with the values read, or why the transaction failed.
+    pub result: Result<Vec<MicroOp>, Error>,
+    /// What other replicas have to install, if anything was written.
+    pub committed: Option<Committed>,
+}
+
+/// A replica of the registers.
+///
+/// A transaction runs as a whole on the node it arrives at, so it is never
+/// interleaved with another one there. All writes of a transaction share one
+/// `Version`, and every replica keeps the write with the highest version
+/// per key: since the versions of two transactions compare the same way on
+/// every key, their writes can never be ordered differently on two keys.
+#[derive(Debug, Clone)]
+pub struct TxnStore {
+    pub isolation: Isolation,
+    node_id: String,
+    clock: u64,
+    values: HashMap<u64, (u64, Version)>,
+}
+
+impl TxnStore {
+    pub fn new(isolation: Isolation, node_id: impl Into<String>) -> Self {
+        TxnStore {
+            isolation,
+            node_id: node_id.into(),
+            clock: 0,
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn read(&self, key: u64) -> Option<u64> {
+        self.values.get(&key).map(|(value, _)| *value)
+    }
+
+    pub fn execute(&mut self, txn: &[MicroOp]) -> Executed {
+        self.clock += 1;
+        let version = Version {
+            counter: self.clock,
+            node: self.node_id.clone(),
+        };
+        let mut written: Vec<(u64, u64)> = Vec::new();
+        let mut done = Vec::with_capacity(txn.len());
+        let mut failure = None;
+        for MicroOp(f, key, value) in txn {
+            match (f, value) {
+                (Function::Read, _) => {
+                    let own = written.iter().rev().find(|(k, _)| k == key);
+                    let value = own.map(|(_, v)| *v).or_else(|| self.read(*key));
+                    done.push(MicroOp(Function::Read, *key, value));
+                }
+                (Function::Write, Some(value)) => {
+                    if self.isolation == Isolation::ReadUncommitted {
+                        self.install(*key, *value, &version);
+                    }
+                    written.push((*key, *value));
+                    done.push(MicroOp(Function::Write, *key, Some(*value)));
+                }
+                (Function::Write, None) => {
+                    failure = Some(Error::new(
+                        ErrorCode::MalformedRequest,
+                        format!("write of key {} without a value", key),
+                    ));
+                    break;
+                }
+            }
+        }
+
+        if failure.is_some() && self.isolation == Isolation::ReadCommitted {
+            written.clear();
+        }
+        let writes = last_writes(written);
+        if self.isolation == Isolation::ReadCommitted {
+            for (key, value) in &writes {
+                self.install(*key, *value, &version);
+            }
+        }
+        Executed {
+            result: match failure {
+                Some(error) => Err(error),
+                None => Ok(done),
+            },
+            committed: (!writes.is_empty()).then_some(Committed { version, writes }),
+        }
+    }
+
+    /// Installs a transaction replicated from another node.
+    pub fn merge(&mut self, committed: &Committed) {
+        self.clock = self.clock.max(committed.version.counter);
+        for (key, value) in &committed.writes {
+            self.install(*key, *value, &committed.version);
+        }
+    }
+
+    fn install(&mut self, key: u64, value: u64, version: &Version) {
+        match self.values.get(&key) {
+            Some((_, current)) if current > version => {}
+            _ => {
+                self.values.insert(key, (value, version.clone()));
+            }
+        }
+    }
+}
+
+/// Keeps only the last write to every key, in the order of first writes.
+fn last_writes(written: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut writes: Vec<(u64, u64)> = Vec::new();
+    for (key, value) in written {
+        match writes.iter_mut().find(|(k, _)| *k == key) {
+            Some(write) => write.1 = value,
+            None => writes.push((key, value)),
+        }
+    }
+    writes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(key: u64) -> MicroOp {
+        MicroOp(Function::Read, key, None)
+    }
+
+    fn w(key: u64, value: u64) -> MicroOp {
+        MicroOp(Function::Write, key, Some(value))
+    }
+
+    fn read_on(store: &mut TxnStore, key: u64) -> Option<u64> {
+        let ops = store.execute(&[r(key)]).result.unwrap();
+        ops[0].2
+    }
+
+    #[test]
+    fn micro_ops_use_the_maelstrom_encoding() {
+        let ops: Vec<MicroOp> = serde_json::from_str(r#"[["r", 1, null], ["w", 2, 3]]"#).unwrap();
+        assert_eq!(ops, vec![r(1), w(2, 3)]);
+        assert_eq!(serde_json::to_string(&w(2, 3)).unwrap(), r#"["w",2,3]"#);
+    }
+
+    /// G0: two transactions writing the same keys must be ordered the same
+    /// way on every key, whatever order replicas see them in.
+    #[test]
+    fn concurrent_writers_do_not_form_a_write_cycle() {
+        for isolation in [Isolation::ReadUncommitted, Isolation::ReadCommitted] {
+            let mut a = TxnStore::new(isolation, "n1");
+            let mut b = TxnStore::new(isolation, "n2");
+            let t1 = a.execute(&[w(1, 1), w(2, 1)]).committed.unwrap();
+            let t2 = b.execute(&[w(2, 2), w(1, 2)]).committed.unwrap();
+            a.merge(&t2);
+            b.merge(&t1);
+
+            for store in [&mut a, &mut b] {
+                let x = read_on(store, 1);
+                let y = read_on(store, 2);
+                assert_eq!(x, y, "{:?}: keys ordered differently", isolation);
+            }
+            assert_eq!(read_on(&mut a, 1), read_on(&mut b, 1));
+        }
+    }
+
+    /// G1a: writes of a failed transaction are never seen under read
+    /// committed.
+    #[test]
+    fn failed_transactions_are_not_read_under_read_committed() {
+        let mut a = TxnStore::new(Isolation::ReadCommitted, "n1");
+        let mut b = TxnStore::new(Isolation::ReadCommitted, "n2");
+        let executed = a.execute(&[w(1, 1), MicroOp(Function::Write, 2, None)]);
+        assert!(executed.result.is_err());
+        assert!(executed.committed.is_none());
+        assert_eq!(read_on(&mut a, 1), None);
+        assert_eq!(read_on(&mut b, 1), None);
+    }
+
+    #[test]
+    fn failed_transactions_leave_writes_under_read_uncommitted() {
+        let mut a = TxnStore::new(Isolation::ReadUncommitted, "n1");
+        let executed = a.execute(&[w(1, 1), MicroOp(Function::Write, 2, None)]);
+        assert!(executed.result.is_err());
+        assert_eq!(executed.committed.unwrap().writes, vec![(1, 1)]);
+        assert_eq!(read_on(&mut a, 1), Some(1));
+    }
+
+    /// G1b: only the final write of a transaction to a key is ever read by
+    /// others, locally or on another replica.
+    #[test]
+    fn intermediate_writes_are_not_read() {
+        let mut a = TxnStore::new(Isolation::ReadCommitted, "n1");
+        let mut b = TxnStore::new(Isolation::ReadCommitted, "n2");
+        let executed = a.execute(&[w(1, 1), r(1), w(1, 2)]);
+        let ops = executed.result.unwrap();
+        assert_eq!(ops[1], MicroOp(Function::Read, 1, Some(1)));
+
+        let committed = executed.committed.unwrap();
+        assert_eq!(committed.writes, vec![(1, 2)]);
+        b.merge(&committed);
+        assert_eq!(read_on(&mut a, 1), Some(2));
+        assert_eq!(read_on(&mut b, 1), Some(2));
+    }
+
+    #[test]
+    fn later_transactions_win_after_merging() {
+        let mut a = TxnStore::new(Isolation::ReadCommitted, "n1");
+        let mut b = TxnStore::new(Isolation::ReadCommitted, "n2");
+        let t1 = a.execute(&[w(1, 1)]).committed.unwrap();
+        b.merge(&t1);
+        let t2 = b.execute(&[w(1, 2)]).committed.unwrap();
+        a.merge(&t2);
+        // A stale copy arriving late must not undo t2.
+        a.merge(&t1);
+        assert_eq!(read_on(&mut a, 1), Some(2));
+    }
+}
@@ -1,16 +1,266 @@
-use std::fmt::Debug;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
 
-use serde::Serialize;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
 
-use crate::Message;
+use crate::{context::NodeContext, rpc::client::RpcError, rpc::retry::RetryPolicy, Message};
 
-#[derive(Debug, Default)]
+/// Whoever is waiting for the reply to a request.
+pub enum Waiter {
+    /// A `ResponseHandle`, filled directly by the stdin reader.
+    Handle(Arc<Slot>),
+    /// A callback kept by the `NodeContext`, run on the node's thread.
+    Callback,
+}
+
+/// What the stdin reader should do with an inbound message.
+pub enum Route {
+    /// The message was handed to a `ResponseHandle`.
+    Delivered,
+    /// The message answers a callback request and has to be completed on the
+    /// node's thread.
+    Callback(Message<Value>),
+    /// Nobody is waiting for the message, it goes to `Node::handle`.
+    Unexpected(Message<Value>),
+}
+
+/// A request whose deadline passed, returned by `ExpectedMessages::expire`.
+pub enum Expired {
+    /// The request has to be written out again.
+    Resend(String),
+    /// The callback request `msg_id` ran out of retries and its callback
+    /// has to be told.
+    TimedOut(usize, RpcError),
+}
+
+struct Pending {
+    waiter: Waiter,
+    request: String,
+    policy: RetryPolicy,
+    attempt: usize,
+    deadline: Instant,
+    /// `deadline` ends a backoff, after which `request` is resent.
+    backing_off: bool,
+}
+
+/// Requests sent through `Rpc` that are still waiting for a reply, keyed by
+/// their `msg_id`.
+#[derive(Default)]
 pub struct ExpectedMessages {
-	pub changed: bool,
-	pub msg_id: Vec<usize>,
-	pub responses: Vec<usize>
+    pending: HashMap<usize, Pending>,
+}
+
+impl ExpectedMessages {
+    /// Starts waiting for a reply to `msg_id`, whose serialized form is
+    /// `request`, assuming it was just sent at `now`.
+    pub fn expect(
+        &mut self,
+        msg_id: usize,
+        waiter: Waiter,
+        request: String,
+        policy: RetryPolicy,
+        now: Instant,
+    ) {
+        self.pending.insert(
+            msg_id,
+            Pending {
+                waiter,
+                request,
+                policy,
+                attempt: 0,
+                deadline: now + policy.timeout,
+                backing_off: false,
+            },
+        );
+    }
+
+    pub fn route(&mut self, message: Message<Value>) -> Route {
+        let Some(msg_id) = message.body.in_reply_to else {
+            return Route::Unexpected(message);
+        };
+        match self.pending.get(&msg_id).map(|p| &p.waiter) {
+            Some(Waiter::Handle(_)) => {
+                let Some(Waiter::Handle(slot)) = self.pending.remove(&msg_id).map(|p| p.waiter)
+                else {
+                    unreachable!()
+                };
+                slot.fill(Ok(message));
+                Route::Delivered
+            }
+            Some(Waiter::Callback) => Route::Callback(message),
+            None => Route::Unexpected(message),
+        }
+    }
+
+    /// Stops waiting for `msg_id` if a callback is waiting for it.
+    pub fn take_callback(&mut self, msg_id: usize) -> bool {
+        match self.pending.get(&msg_id).map(|p| &p.waiter) {
+            Some(Waiter::Callback) => {
+                self.pending.remove(&msg_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The earliest point at which `expire` has something to do.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|p| p.deadline).min()
+    }
+
+    /// Advances every request whose deadline is at or before `now`: timed out
+    /// attempts start backing off or give up, finished backoffs are resent.
+    /// Handles that give up are failed in place, everything else is returned
+    /// for the caller to act on, oldest request first. Backoffs are jittered
+    /// with `rng`.
+    pub fn expire(&mut self, now: Instant, rng: &mut impl Rng) -> Vec<Expired> {
+        let mut due: Vec<usize> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.deadline <= now)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+        due.sort_unstable();
+
+        let mut expired = Vec::new();
+        for msg_id in due {
+            let pending = self.pending.get_mut(&msg_id).unwrap();
+            if pending.backing_off {
+                pending.attempt += 1;
+                pending.backing_off = false;
+                pending.deadline = now + pending.policy.timeout;
+                expired.push(Expired::Resend(pending.request.clone()));
+            } else if pending.attempt < pending.policy.retries {
+                pending.backing_off = true;
+                pending.deadline = now + pending.policy.backoff(pending.attempt, rng);
+            } else {
+                let pending = self.pending.remove(&msg_id).unwrap();
+                let error = RpcError::Timeout {
+                    msg_id,
+                    attempts: pending.attempt + 1,
+                };
+                match pending.waiter {
+                    Waiter::Handle(slot) => slot.fill(Err(error.into())),
+                    Waiter::Callback => expired.push(Expired::TimedOut(msg_id, error)),
+                }
+            }
+        }
+        expired
+    }
+}
+
+#[derive(Default)]
+pub struct Slot {
+    state: Mutex<SlotState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct SlotState {
+    reply: Option<anyhow::Result<Message<Value>>>,
+    waker: Option<Waker>,
+}
+
+impl Slot {
+    pub fn fill(&self, reply: anyhow::Result<Message<Value>>) {
+        let mut state = self.state.lock().unwrap();
+        state.reply = Some(reply);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
 }
 
-fn await_responses<P: Debug + Serialize>(messages: Vec<Message<P>>) {
+/// The pending reply to a request sent with `Rpc::call`.
+///
+/// Replies are delivered by the stdin reader thread, so a handle can be
+/// waited on from inside `Node::handle` or polled as a future. A future only
+/// sees retries and timeouts while `main_loop` is running, `wait` drives them
+/// itself.
+pub struct ResponseHandle<R> {
+    slot: Arc<Slot>,
+    _reply: PhantomData<fn() -> R>,
+}
+
+impl<R: DeserializeOwned + Serialize + Debug> ResponseHandle<R> {
+    pub fn new(slot: Arc<Slot>) -> Self {
+        ResponseHandle {
+            slot,
+            _reply: PhantomData,
+        }
+    }
 
-}
\ No newline at end of file
+    /// Blocks until the reply arrives or the request times out, resending it
+    /// as its `RetryPolicy` says. Callback requests of `ctx` that give up
+    /// meanwhile are failed as well.
+    ///
+    /// Nothing arrives while a simulated node blocks, so on a virtual clock
+    /// this fails unless the reply is already there.
+    pub fn wait<T>(mut self, ctx: &mut NodeContext<T>) -> anyhow::Result<Message<R>> {
+        if ctx.clock.is_virtual() {
+            return self.try_take().unwrap_or_else(|| {
+                Err(anyhow::anyhow!(
+                    "cannot block on a reply in a simulation, use NodeContext::call_with"
+                ))
+            });
+        }
+        loop {
+            // Look up the deadline before taking the slot lock, the reader
+            // thread locks the two in the opposite order.
+            let deadline = ctx.rpc.next_deadline();
+            let state = self.slot.state.lock().unwrap();
+            let mut state = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(ctx.now());
+                    self.slot
+                        .ready
+                        .wait_timeout_while(state, timeout, |s| s.reply.is_none())
+                        .unwrap()
+                        .0
+                }
+                None => self
+                    .slot
+                    .ready
+                    .wait_while(state, |s| s.reply.is_none())
+                    .unwrap(),
+            };
+            if let Some(reply) = state.reply.take() {
+                return reply?.decode_reply();
+            }
+            drop(state);
+            ctx.expire(ctx.now())?;
+        }
+    }
+
+    /// Returns the reply if it has already arrived.
+    pub fn try_take(&mut self) -> Option<anyhow::Result<Message<R>>> {
+        let reply = self.slot.state.lock().unwrap().reply.take()?;
+        Some(reply.and_then(Message::decode_reply))
+    }
+}
+
+impl<R: DeserializeOwned + Serialize + Debug> Future for ResponseHandle<R> {
+    type Output = anyhow::Result<Message<R>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock().unwrap();
+        match state.reply.take() {
+            Some(reply) => Poll::Ready(reply.and_then(Message::decode_reply)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}